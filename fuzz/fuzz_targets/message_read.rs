@@ -0,0 +1,7 @@
+//! Feeds arbitrary bytes to the message parser, run with `cargo fuzz run message_read`.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    dbus_native::fuzz::message_read(data);
+});
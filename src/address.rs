@@ -1,3 +1,115 @@
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::ffi::OsString;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::{self, TcpStream, ToSocketAddrs};
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+#[cfg(unix)]
+use std::os::unix::net::{SocketAddr, UnixListener};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::net::TcpListener;
+
+    fn runtime_addr() -> UnixDomainSocketAddr {
+        UnixDomainSocketAddr {
+            runtime: Some("yes".to_string()),
+            ..UnixDomainSocketAddr::default()
+        }
+    }
+
+    fn tmpdir_addr() -> UnixDomainSocketAddr {
+        UnixDomainSocketAddr {
+            tmpdir: Some("/tmp".to_string()),
+            ..UnixDomainSocketAddr::default()
+        }
+    }
+
+    #[test]
+    fn resolve_runtime() {
+        let resolved = runtime_addr()
+            .resolve_runtime_in(Some(OsString::from("/run/user/1000")))
+            .unwrap();
+        assert_eq!(
+            Some("/run/user/1000/bus".to_string()),
+            resolved.and_then(|addr| addr.path)
+        );
+
+        assert_eq!(None, runtime_addr().resolve_runtime_in(None).unwrap());
+
+        let invalid = UnixDomainSocketAddr {
+            runtime: Some("no".to_string()),
+            ..UnixDomainSocketAddr::default()
+        };
+        assert!(invalid.resolve_runtime_in(None).is_err());
+    }
+
+    #[test]
+    fn listen_dir() {
+        let server_addr = UnixDomainSocketAddr {
+            dir: Some(env::temp_dir().to_str().unwrap().to_string()),
+            ..UnixDomainSocketAddr::default()
+        };
+
+        let (_listener, client_addr) = server_addr.listen().unwrap();
+        let path = client_addr.path.unwrap();
+        assert!(Path::new(&path).exists());
+        let name = Path::new(&path).file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("dbus-") && name.len() == 13, "{}", name);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn listen_tmpdir() {
+        let (_listener, client_addr) = tmpdir_addr().listen().unwrap();
+        assert_eq!(None, client_addr.path);
+        assert!(client_addr.r#abstract.unwrap().starts_with("/tmp/dbus-"));
+    }
+
+    #[test]
+    fn connect_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = TcpSocketAddr {
+            host: Some("127.0.0.1".to_string()),
+            bind: None,
+            port: Some(listener.local_addr().unwrap().port()),
+            family: Some("ipv4".to_string()),
+        };
+        assert!(addr.connect().is_ok());
+    }
+
+    #[test]
+    fn connect_staggered_skips_failed_attempts() {
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+
+        let stream = connect_staggered(vec![closed, open], Duration::from_secs(10)).unwrap();
+        assert_eq!(open, stream.peer_addr().unwrap());
+    }
+
+    #[test]
+    fn resolve_runtime_fallback() {
+        let addresses = [runtime_addr(), tmpdir_addr()];
+        assert_eq!(
+            Some(tmpdir_addr()),
+            resolve_first_in(&addresses, None).unwrap()
+        );
+    }
+}
+
 /// The address of the system message bus is given in the DBUS_SYSTEM_BUS_ADDRESS environment variable.
 /// If that variable is not set, applications should try to connect to the well-known address unix:path=/var/run/dbus/system_bus_socket
 const WELL_KNOWN_DBUS_SYSTEM_BUS_ENV: &str = "DBUS_SYSTEM_BUS_ADDRESS";
@@ -6,17 +118,23 @@ const WELL_KNOWN_DBUS_SYSTEM_BUS_ENV: &str = "DBUS_SYSTEM_BUS_ADDRESS";
 /// If that variable is not set, applications should try to connect to the well-known address unix:path=/var/run/dbus/system_bus_socket
 const WELL_KNOWN_DBUS_SYSTEM_BUS_ADDRESS: &str = "unix:path=/var/run/dbus/system_bus_socket";
 
+/// The directory holding the per-user bus socket when an address contains `runtime=yes`.
+const XDG_RUNTIME_DIR_ENV: &str = "XDG_RUNTIME_DIR";
+
 trait ServerAddress {
     fn to_address(&self) -> String;
 }
 
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 struct UnixDomainSocketAddr {
+    /// Path of the unix domain socket
+    pub path: Option<String>,
     ///  Directory in which a socket file with a random file
     /// name starting with 'dbus-' will be created by the server.
     /// This key can only be used in server addresses, not in client
     /// addresses; the resulting client address will have the "path" key
     /// instead. be set.
-    pub path: Option<String>,
+    pub dir: Option<String>,
     /// The same as "dir", except that on platforms with abstract
     /// sockets, the server may attempt to create an abstract
     /// socket whose name starts with this directory instead of a
@@ -45,6 +163,10 @@ impl ServerAddress for UnixDomainSocketAddr {
             pairs.push(format!("path={}", path));
         }
 
+        if let Some(dir) = self.dir.as_ref() {
+            pairs.push(format!("dir={}", dir));
+        }
+
         if let Some(tmpdir) = self.tmpdir.as_ref() {
             pairs.push(format!("tmpdir={}", tmpdir));
         }
@@ -60,6 +182,183 @@ impl ServerAddress for UnixDomainSocketAddr {
     }
 }
 
+/// Returns the first of `addresses` that is usable, addresses with `runtime=yes`
+/// are skipped if XDG_RUNTIME_DIR is not set.
+fn resolve_first(addresses: &[UnixDomainSocketAddr]) -> io::Result<Option<UnixDomainSocketAddr>> {
+    resolve_first_in(addresses, env::var_os(XDG_RUNTIME_DIR_ENV))
+}
+
+fn resolve_first_in(
+    addresses: &[UnixDomainSocketAddr],
+    runtime_dir: Option<OsString>,
+) -> io::Result<Option<UnixDomainSocketAddr>> {
+    for address in addresses {
+        if let Some(resolved) = address.resolve_runtime_in(runtime_dir.clone())? {
+            return Ok(Some(resolved));
+        }
+    }
+    Ok(None)
+}
+
+impl UnixDomainSocketAddr {
+    /// Replaces `runtime=yes` by the path of the `bus` socket in XDG_RUNTIME_DIR.
+    /// Returns `None` if XDG_RUNTIME_DIR is not set, in that case the next
+    /// address of the address list must be tried.
+    fn resolve_runtime(&self) -> io::Result<Option<UnixDomainSocketAddr>> {
+        self.resolve_runtime_in(env::var_os(XDG_RUNTIME_DIR_ENV))
+    }
+
+    fn resolve_runtime_in(
+        &self,
+        runtime_dir: Option<OsString>,
+    ) -> io::Result<Option<UnixDomainSocketAddr>> {
+        match self.runtime.as_deref() {
+            None => Ok(Some(self.clone())),
+            Some("yes") => {
+                let runtime_dir = match runtime_dir {
+                    Some(dir) if !dir.is_empty() => dir,
+                    _ => return Ok(None),
+                };
+
+                let path = Path::new(&runtime_dir).join("bus");
+                let path = path.to_str().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "XDG_RUNTIME_DIR is not valid UTF-8",
+                    )
+                })?;
+
+                Ok(Some(UnixDomainSocketAddr {
+                    path: Some(path.to_string()),
+                    runtime: None,
+                    ..self.clone()
+                }))
+            }
+            Some(runtime) => {
+                let str_err = format!("Invalid runtime value `{}`, must be `yes`", runtime);
+                Err(io::Error::new(io::ErrorKind::InvalidInput, str_err))
+            }
+        }
+    }
+}
+
+/// Number of random socket names tried before giving up when listening on `dir` or `tmpdir`.
+const MAX_LISTEN_ATTEMPTS: usize = 16;
+
+/// A random socket file name of the form `dbus-XXXXXXXX`.
+fn random_socket_name() -> String {
+    const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+    let mut seed = RandomState::new().build_hasher().finish();
+
+    let mut name = String::from("dbus-");
+    for _ in 0..8 {
+        name.push(CHARS[(seed % CHARS.len() as u64) as usize] as char);
+        seed /= CHARS.len() as u64;
+    }
+    name
+}
+
+#[cfg(unix)]
+impl UnixDomainSocketAddr {
+    /// Creates the listening socket for this server address and returns it together with
+    /// the address clients have to use. For `dir` and `tmpdir` a socket with a random name
+    /// is created, the client address has the "path" or (for `tmpdir` on Linux) the
+    /// "abstract" key instead.
+    fn listen(&self) -> io::Result<(UnixListener, UnixDomainSocketAddr)> {
+        if let Some(path) = self.path.as_ref() {
+            let listener = UnixListener::bind(path)?;
+            return Ok((listener, UnixDomainSocketAddr::client_path(path.clone())));
+        }
+
+        if let Some(r#abstract) = self.r#abstract.as_ref() {
+            let listener = UnixListener::bind_addr(&abstract_socket_addr(r#abstract)?)?;
+            return Ok((
+                listener,
+                UnixDomainSocketAddr::client_abstract(r#abstract.clone()),
+            ));
+        }
+
+        if let Some(dir) = self.dir.as_ref() {
+            return listen_random(|name| {
+                let path = socket_path(dir, name)?;
+                let listener = UnixListener::bind(&path)?;
+                Ok((listener, UnixDomainSocketAddr::client_path(path)))
+            });
+        }
+
+        if let Some(tmpdir) = self.tmpdir.as_ref() {
+            return listen_random(|name| {
+                let path = socket_path(tmpdir, name)?;
+                if cfg!(target_os = "linux") {
+                    let listener = UnixListener::bind_addr(&abstract_socket_addr(&path)?)?;
+                    Ok((listener, UnixDomainSocketAddr::client_abstract(path)))
+                } else {
+                    let listener = UnixListener::bind(&path)?;
+                    Ok((listener, UnixDomainSocketAddr::client_path(path)))
+                }
+            });
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Unix address requires one of path, abstract, dir or tmpdir to listen",
+        ))
+    }
+
+    fn client_path(path: String) -> UnixDomainSocketAddr {
+        UnixDomainSocketAddr {
+            path: Some(path),
+            ..UnixDomainSocketAddr::default()
+        }
+    }
+
+    fn client_abstract(r#abstract: String) -> UnixDomainSocketAddr {
+        UnixDomainSocketAddr {
+            r#abstract: Some(r#abstract),
+            ..UnixDomainSocketAddr::default()
+        }
+    }
+}
+
+/// Calls `bind` with fresh random socket names until one is not in use yet.
+#[cfg(unix)]
+fn listen_random<F>(bind: F) -> io::Result<(UnixListener, UnixDomainSocketAddr)>
+where
+    F: Fn(&str) -> io::Result<(UnixListener, UnixDomainSocketAddr)>,
+{
+    for _ in 0..MAX_LISTEN_ATTEMPTS {
+        match bind(&random_socket_name()) {
+            Err(ref err) if err.kind() == io::ErrorKind::AddrInUse => continue,
+            result => return result,
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AddrInUse,
+        "Failed to find an unused socket name",
+    ))
+}
+
+fn socket_path(dir: &str, name: &str) -> io::Result<String> {
+    Path::new(dir)
+        .join(name)
+        .to_str()
+        .map(str::to_string)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Socket path is not valid UTF-8"))
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_socket_addr(name: &str) -> io::Result<SocketAddr> {
+    SocketAddr::from_abstract_name(name.as_bytes())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn abstract_socket_addr(_name: &str) -> io::Result<SocketAddr> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Abstract sockets are only supported on Linux",
+    ))
+}
+
 struct TcpSocketAddr {
     /// DNS name or IP address
     pub host: Option<String>,
@@ -76,6 +375,95 @@ struct TcpSocketAddr {
     pub family: Option<String>,
 }
 
+/// Delay before the next connection attempt is started while the previous ones are still pending.
+const CONNECT_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+impl TcpSocketAddr {
+    /// Connects to the server. If the host resolves to several addresses, connection
+    /// attempts are started one after another with a short delay, alternating between
+    /// IPv6 and IPv4, and the first established connection is used.
+    fn connect(&self) -> io::Result<TcpStream> {
+        let host = self.host.as_ref().map_or("localhost", String::as_str);
+        let port = self.port.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "TCP address requires a port")
+        })?;
+
+        let resolved = (host, port).to_socket_addrs()?;
+        let addrs: Vec<net::SocketAddr> = match self.family.as_deref() {
+            None => interleave_families(resolved.collect()),
+            Some("ipv4") => resolved.filter(net::SocketAddr::is_ipv4).collect(),
+            Some("ipv6") => resolved.filter(net::SocketAddr::is_ipv6).collect(),
+            Some(family) => {
+                let str_err = format!("Invalid family `{}`, must be `ipv4` or `ipv6`", family);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, str_err));
+            }
+        };
+
+        connect_staggered(addrs, CONNECT_ATTEMPT_DELAY)
+    }
+}
+
+/// Orders addresses alternating between IPv6 and IPv4, starting with the family of the first address.
+fn interleave_families(addrs: Vec<net::SocketAddr>) -> Vec<net::SocketAddr> {
+    let first_is_ipv6 = addrs.first().is_some_and(net::SocketAddr::is_ipv6);
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_is_ipv6);
+
+    let mut interleaved = Vec::with_capacity(preferred.len() + other.len());
+    preferred.reverse();
+    other.reverse();
+    while !preferred.is_empty() || !other.is_empty() {
+        interleaved.extend(preferred.pop());
+        interleaved.extend(other.pop());
+    }
+    interleaved
+}
+
+/// Starts a connection attempt per address, each after the previous one failed or
+/// `delay` passed, and returns the first connection that is established.
+fn connect_staggered(addrs: Vec<net::SocketAddr>, delay: Duration) -> io::Result<TcpStream> {
+    let (sender, receiver) = mpsc::channel();
+    let mut pending = 0;
+    let mut last_err = None;
+
+    for addr in addrs {
+        let sender = sender.clone();
+        thread::spawn(move || {
+            // The receiver is gone once another attempt succeeded, the stream is closed then.
+            let _ = sender.send(TcpStream::connect(addr));
+        });
+        pending += 1;
+
+        match receiver.recv_timeout(delay) {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(err)) => {
+                pending -= 1;
+                last_err = Some(err);
+            }
+            Err(_) => {}
+        }
+    }
+
+    while pending > 0 {
+        match receiver.recv() {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(err)) => {
+                pending -= 1;
+                last_err = Some(err);
+            }
+            Err(_) => break,
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "Host did not resolve to any address",
+        )
+    }))
+}
+
 impl ServerAddress for TcpSocketAddr {
     fn to_address(&self) -> String {
         let mut pairs = Vec::new();
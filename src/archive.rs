@@ -0,0 +1,342 @@
+//! A simple file format for storing sequences of messages, e.g. for recording and replaying
+//! traffic. The file starts with a magic number followed by one record per message, each
+//! record is the length of the message as little-endian UINT32 followed by the message in wire
+//! format, exactly as received for parsed messages.
+//! `MessageRing` keeps only the most recent messages in memory until they are exported as archive.
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::collections::VecDeque;
+use std::io;
+use std::io::Read;
+
+use crate::limits::MAX_MESSAGE_SIZE;
+use crate::message::Message;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::limits::Limits;
+    use crate::message::SIGNAL;
+    use crate::reader::ParseOptions;
+    use byteorder::ReadBytesExt;
+
+    fn archive(records: usize) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        for _ in 0..records {
+            bytes.extend_from_slice(&(SIGNAL.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&SIGNAL);
+        }
+        bytes
+    }
+
+    #[test]
+    fn read_archive() {
+        let bytes = archive(2);
+        let reader = ArchiveReader::new(bytes.as_slice()).unwrap();
+        let messages: Vec<Message> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(2, messages.len());
+        assert_eq!(messages[0], Message::from_reader(&SIGNAL[..]).unwrap());
+    }
+
+    #[test]
+    fn read_archive_errors() {
+        assert!(ArchiveReader::new(&b"DBUSMSG0"[..]).is_err());
+
+        let bytes = archive(1);
+        let mut reader = ArchiveReader::new(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(reader.next().unwrap().is_err());
+
+        let mut reader = ArchiveReader::new(&bytes[..MAGIC.len() + 2]).unwrap();
+        assert!(reader.next().unwrap().is_err());
+
+        // The record is longer than the message it contains
+        let mut bytes = archive(1);
+        bytes[MAGIC.len()] += 1;
+        bytes.push(0);
+        let mut reader = ArchiveReader::new(bytes.as_slice()).unwrap();
+        assert!(reader.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn message_ring() {
+        let message = Message::from_reader(&SIGNAL[..]).unwrap();
+        let mut ring = MessageRing::new(2, 1024);
+        for _ in 0..3 {
+            ring.push(&message).unwrap();
+        }
+        assert_eq!(2, ring.len());
+        assert_eq!(2 * SIGNAL.len(), ring.bytes());
+        assert_eq!(1, ring.overwritten());
+        assert_eq!(archive(2), ring.snapshot(Vec::new()).unwrap());
+
+        let mut ring = MessageRing::new(16, 2 * SIGNAL.len() + 1);
+        for _ in 0..3 {
+            ring.push(&message).unwrap();
+        }
+        assert_eq!(2, ring.len());
+
+        let mut ring = MessageRing::new(16, SIGNAL.len() - 1);
+        ring.push(&message).unwrap();
+        assert_eq!(0, ring.len());
+        assert_eq!(0, ring.bytes());
+        assert_eq!(1, ring.skipped());
+        assert_eq!(archive(0), ring.snapshot(Vec::new()).unwrap());
+
+        // An oversized message leaves the messages held before intact
+        let mut bytes = SIGNAL[..96].to_vec();
+        bytes[4..8].copy_from_slice(&305u32.to_le_bytes());
+        bytes.extend_from_slice(&300u32.to_le_bytes());
+        bytes.extend_from_slice(&[b'a'; 300]);
+        bytes.push(0);
+        let large = Message::from_reader(bytes.as_slice()).unwrap();
+        let mut ring = MessageRing::new(16, 3 * SIGNAL.len());
+        ring.push(&message).unwrap();
+        ring.push(&message).unwrap();
+        ring.push(&large).unwrap();
+        assert_eq!(2, ring.len());
+        assert_eq!(2 * SIGNAL.len(), ring.bytes());
+        assert_eq!(0, ring.overwritten());
+        assert_eq!(1, ring.skipped());
+        assert_eq!(archive(2), ring.snapshot(Vec::new()).unwrap());
+    }
+
+    #[test]
+    fn message_ring_received_bytes() {
+        let lenient = ParseOptions::lenient();
+        let mut unknown = SIGNAL.to_vec();
+        unknown[1] = 5;
+        // The interface reserved for locally generated messages, padded to the same length
+        let mut local = SIGNAL.to_vec();
+        local[36..40].copy_from_slice(&26u32.to_le_bytes());
+        local[40..72].copy_from_slice(b"org.freedesktop.DBus.Local\0\0\0\0\0\0");
+
+        let mut ring = MessageRing::new(16, 1024);
+        let mut expected = MAGIC.to_vec();
+        for bytes in [unknown, local].iter() {
+            let message = Message::read_with_options(bytes.as_slice(), lenient, Limits::default());
+            ring.push(&message.unwrap()).unwrap();
+            expected.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            expected.extend_from_slice(bytes);
+        }
+        assert_eq!(2, ring.len());
+        assert_eq!(expected, ring.snapshot(Vec::new()).unwrap());
+    }
+
+    #[test]
+    fn write_archive() {
+        let message = Message::from_reader(&SIGNAL[..]).unwrap();
+        let mut buffer = Vec::new();
+        let mut writer = ArchiveWriter::new(&mut buffer).unwrap();
+        writer.append(&message).unwrap();
+        writer.append(&message).unwrap();
+
+        let mut reader = &buffer[..];
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic).unwrap();
+        assert_eq!(MAGIC, &magic);
+        for _ in 0..2 {
+            let len = reader.read_u32::<LittleEndian>().unwrap() as usize;
+            reader = &reader[len..];
+        }
+        assert!(reader.is_empty());
+        assert_eq!(archive(2), buffer);
+    }
+
+    #[test]
+    fn write_archive_received_bytes() {
+        // A message of an unknown type, which can not be sent
+        let mut bytes = SIGNAL.to_vec();
+        bytes[1] = 5;
+        let lenient = ParseOptions::lenient();
+        let message = Message::read_with_options(bytes.as_slice(), lenient, Limits::default());
+
+        let mut buffer = Vec::new();
+        let mut writer = ArchiveWriter::new(&mut buffer).unwrap();
+        writer.append(&message.unwrap()).unwrap();
+        let mut expected = MAGIC.to_vec();
+        expected.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        expected.extend_from_slice(&bytes);
+        assert_eq!(expected, buffer);
+    }
+}
+
+/// Identifies the file format and its version.
+const MAGIC: &[u8; 8] = b"DBUSMSG1";
+
+/// Writes messages in the archive format.
+pub(crate) struct ArchiveWriter<T: io::Write> {
+    writer: T,
+}
+
+impl<T: io::Write> ArchiveWriter<T> {
+    /// Starts a new archive by writing the magic number.
+    pub(crate) fn new(mut writer: T) -> Result<ArchiveWriter<T>, io::Error> {
+        writer.write_all(MAGIC)?;
+        Ok(ArchiveWriter { writer })
+    }
+
+    /// Appends a message, see `Message::to_writer`. Any received message can be appended,
+    /// including those that could not be sent as they are.
+    pub(crate) fn append(&mut self, message: &Message) -> Result<(), io::Error> {
+        let mut buffer = Vec::new();
+        message.to_writer(&mut buffer)?;
+        self.append_record(&buffer)
+    }
+
+    /// Appends an already marshaled message.
+    fn append_record(&mut self, record: &[u8]) -> Result<(), io::Error> {
+        self.writer.write_u32::<LittleEndian>(record.len() as u32)?;
+        self.writer.write_all(record)
+    }
+
+    pub(crate) fn into_inner(self) -> T {
+        self.writer
+    }
+}
+
+/// Reads the messages of an archive in the order they were appended.
+pub(crate) struct ArchiveReader<T: io::Read> {
+    reader: T,
+}
+
+impl<T: io::Read> ArchiveReader<T> {
+    /// Opens an archive, failing if it does not start with the magic number.
+    pub(crate) fn new(mut reader: T) -> Result<ArchiveReader<T>, io::Error> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a message archive",
+            ));
+        }
+        Ok(ArchiveReader { reader })
+    }
+
+    fn read_record(&mut self, len: u32) -> Result<Message, io::Error> {
+        if len > MAX_MESSAGE_SIZE {
+            let str_err = format!("Record length `{}` exceeds maximum message size", len);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+        }
+
+        let mut record = Vec::new();
+        (&mut self.reader)
+            .take(u64::from(len))
+            .read_to_end(&mut record)?;
+        if record.len() != len as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Archive ends within a record",
+            ));
+        }
+
+        let mut message_bytes = record.as_slice();
+        let message = Message::from_reader(&mut message_bytes)?;
+        if !message_bytes.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Record contains data beyond its message",
+            ));
+        }
+        Ok(message)
+    }
+}
+
+impl<T: io::Read> Iterator for ArchiveReader<T> {
+    type Item = Result<Message, io::Error>;
+
+    /// The next message, or `None` once the archive ends at a record boundary.
+    fn next(&mut self) -> Option<Result<Message, io::Error>> {
+        let mut len = Vec::with_capacity(4);
+        if let Err(err) = (&mut self.reader).take(4).read_to_end(&mut len) {
+            return Some(Err(err));
+        }
+        match len.as_slice() {
+            [] => None,
+            [b0, b1, b2, b3] => Some(self.read_record(u32::from_le_bytes([*b0, *b1, *b2, *b3]))),
+            _ => Some(Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Archive ends within a record length",
+            ))),
+        }
+    }
+}
+
+/// Keeps the most recent messages, up to a number of messages and a number of marshaled bytes.
+/// Once either limit is reached the oldest messages are overwritten, so memory stays bounded
+/// however long traffic is captured.
+pub(crate) struct MessageRing {
+    records: VecDeque<Vec<u8>>,
+    max_messages: usize,
+    max_bytes: usize,
+    bytes: usize,
+    overwritten: u64,
+    skipped: u64,
+}
+
+impl MessageRing {
+    pub(crate) fn new(max_messages: usize, max_bytes: usize) -> MessageRing {
+        MessageRing {
+            records: VecDeque::new(),
+            max_messages,
+            max_bytes,
+            bytes: 0,
+            overwritten: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Adds a message, overwriting the oldest messages as necessary to stay within the limits.
+    /// A message exceeding the byte limit on its own is skipped, keeping the messages held so far.
+    /// Parsed messages are kept as received, see `Message::to_writer`.
+    pub(crate) fn push(&mut self, message: &Message) -> Result<(), io::Error> {
+        let mut record = Vec::new();
+        message.to_writer(&mut record)?;
+        if record.len() > self.max_bytes {
+            self.skipped += 1;
+            return Ok(());
+        }
+        self.bytes += record.len();
+        self.records.push_back(record);
+        while self.records.len() > self.max_messages || self.bytes > self.max_bytes {
+            match self.records.pop_front() {
+                Some(oldest) => {
+                    self.bytes -= oldest.len();
+                    self.overwritten += 1;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of messages currently held.
+    pub(crate) fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// The marshaled size of the messages currently held.
+    pub(crate) fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// The number of messages dropped to make room for newer ones.
+    pub(crate) fn overwritten(&self) -> u64 {
+        self.overwritten
+    }
+
+    /// The number of messages not kept because they alone exceed the byte limit.
+    pub(crate) fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Writes the messages currently held as archive, oldest first. The ring is left unchanged
+    /// so capturing can continue.
+    pub(crate) fn snapshot<T: io::Write>(&self, writer: T) -> Result<T, io::Error> {
+        let mut archive = ArchiveWriter::new(writer)?;
+        for record in self.records.iter() {
+            archive.append_record(record)?;
+        }
+        Ok(archive.into_inner())
+    }
+}
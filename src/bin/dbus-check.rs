@@ -0,0 +1,43 @@
+//! Checks captured messages for well-formedness and prints them in human readable form.
+//! Each file is either a sequence of raw marshaled messages or a pcap capture as written
+//! by `dbus-monitor --pcap`. Exits with status 1 if any file is malformed.
+use std::env;
+use std::fs;
+use std::process;
+
+use dbus_native::check::check;
+
+fn main() {
+    let paths: Vec<String> = env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("Usage: dbus-check FILE...");
+        process::exit(2);
+    }
+
+    let mut malformed = false;
+    for path in paths.iter() {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                process::exit(2);
+            }
+        };
+
+        let report = check(&bytes);
+        for message in report.messages.iter() {
+            println!("{}", message);
+        }
+        match report.malformation {
+            Some(malformation) => {
+                eprintln!("{}: {}", path, malformation);
+                malformed = true;
+            }
+            None => eprintln!("{}: {} messages well-formed", path, report.messages.len()),
+        }
+    }
+
+    if malformed {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,235 @@
+//! Well-formedness checks for captured traffic, e.g. to find out why another implementation
+//! rejects a message. Input is either a sequence of raw marshaled messages or a pcap capture
+//! as written by `dbus-monitor --pcap`, in which each packet holds exactly one message.
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use std::fmt;
+use std::io;
+
+use crate::limits::Limits;
+use crate::message::Message;
+use crate::pretty::PrettyOptions;
+use crate::reader::ParseOptions;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::limits::MAX_MESSAGE_SIZE;
+    use crate::message::SIGNAL;
+
+    fn pcap(packets: &[&[u8]]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        bytes.extend_from_slice(&[2, 0, 4, 0]);
+        bytes.extend_from_slice(&[0; 8]);
+        bytes.extend_from_slice(&MAX_MESSAGE_SIZE.to_le_bytes());
+        bytes.extend_from_slice(&LINKTYPE_DBUS.to_le_bytes());
+        for packet in packets {
+            bytes.extend_from_slice(&[0; 8]);
+            bytes.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(packet);
+        }
+        bytes
+    }
+
+    #[test]
+    fn check_raw_messages() {
+        let mut bytes = SIGNAL.to_vec();
+        bytes.extend_from_slice(&SIGNAL);
+        let report = check(&bytes);
+        assert_eq!(2, report.messages.len());
+        assert!(report.messages[0].starts_with("signal serial=1 path=/path"));
+        assert!(report.malformation.is_none());
+    }
+
+    #[test]
+    fn check_raw_malformed() {
+        let mut bytes = SIGNAL.to_vec();
+        bytes.extend_from_slice(&SIGNAL);
+        // Protocol version of the second message
+        bytes[SIGNAL.len() + 3] = 2;
+        let report = check(&bytes);
+        assert_eq!(1, report.messages.len());
+        let malformation = report.malformation.unwrap();
+        assert_eq!(SIGNAL.len() as u64, malformation.message_offset);
+        assert_eq!(SIGNAL.len() as u64 + 4, malformation.offset);
+
+        let report = check(&SIGNAL[..SIGNAL.len() - 1]);
+        assert!(report.messages.is_empty());
+        assert_eq!(0, report.malformation.unwrap().message_offset);
+    }
+
+    #[test]
+    fn check_pcap() {
+        let report = check(&pcap(&[&SIGNAL, &SIGNAL]));
+        assert_eq!(2, report.messages.len());
+        assert!(report.malformation.is_none());
+
+        // Trailing data after the message within a packet
+        let mut packet = SIGNAL.to_vec();
+        packet.push(0);
+        let report = check(&pcap(&[&SIGNAL, &packet]));
+        assert_eq!(2, report.messages.len());
+        let malformation = report.malformation.unwrap();
+        let packet_offset = (PCAP_HEADER_LEN + 2 * PCAP_RECORD_HEADER_LEN + SIGNAL.len()) as u64;
+        assert_eq!(packet_offset, malformation.message_offset);
+        assert_eq!(packet_offset + SIGNAL.len() as u64, malformation.offset);
+
+        let bytes = pcap(&[&SIGNAL]);
+        let report = check(&bytes[..bytes.len() - 1]);
+        assert!(report.messages.is_empty());
+        assert_eq!(PCAP_HEADER_LEN as u64, report.malformation.unwrap().offset);
+    }
+}
+
+/// Magic number of pcap files with microsecond timestamps.
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+
+/// Magic number of pcap files with nanosecond timestamps.
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+
+/// Link type of captures in which each packet is a single D-Bus message.
+const LINKTYPE_DBUS: u32 = 231;
+
+const PCAP_HEADER_LEN: usize = 24;
+
+const PCAP_RECORD_HEADER_LEN: usize = 16;
+
+/// The outcome of checking a capture. Checking stops at the first malformation.
+#[derive(Debug)]
+pub struct Report {
+    /// The well-formed messages preceding any malformation, in human readable form.
+    pub messages: Vec<String>,
+    /// The first malformation found, if any.
+    pub malformation: Option<Malformation>,
+}
+
+/// Where and why a capture is malformed.
+#[derive(Debug)]
+pub struct Malformation {
+    /// Offset of the start of the malformed message or pcap record within the capture.
+    pub message_offset: u64,
+    /// Offset within the capture at which parsing stopped. Parsing consumes whole strings
+    /// and bodies, so the offending byte may precede this offset within the same element.
+    pub offset: u64,
+    pub reason: io::Error,
+}
+
+impl fmt::Display for Malformation {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Malformed at offset {} (message at offset {}): {}",
+            self.offset, self.message_offset, self.reason
+        )
+    }
+}
+
+/// Checks a capture, which is treated as pcap file if it starts with a pcap magic number
+/// and as sequence of raw messages otherwise. Messages are parsed strictly.
+#[inline]
+pub fn check(bytes: &[u8]) -> Report {
+    let mut report = Report {
+        messages: Vec::new(),
+        malformation: None,
+    };
+    let result = if bytes.len() >= 4 && pcap_byte_order(bytes).is_some() {
+        check_pcap(bytes, &mut report)
+    } else {
+        check_raw(bytes, &mut report)
+    };
+    report.malformation = result.err();
+    report
+}
+
+/// Whether a pcap file is little endian, based on its magic number.
+fn pcap_byte_order(bytes: &[u8]) -> Option<bool> {
+    let magic = LittleEndian::read_u32(bytes);
+    if magic == PCAP_MAGIC || magic == PCAP_MAGIC_NANOS {
+        Some(true)
+    } else if magic.swap_bytes() == PCAP_MAGIC || magic.swap_bytes() == PCAP_MAGIC_NANOS {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn check_pcap(bytes: &[u8], report: &mut Report) -> Result<(), Malformation> {
+    let read_u32 = if pcap_byte_order(bytes) == Some(true) {
+        LittleEndian::read_u32
+    } else {
+        BigEndian::read_u32
+    };
+    let malformation = |offset: usize, reason: &str| Malformation {
+        message_offset: offset as u64,
+        offset: offset as u64,
+        reason: io::Error::new(io::ErrorKind::InvalidData, reason.to_string()),
+    };
+
+    if bytes.len() < PCAP_HEADER_LEN {
+        return Err(malformation(0, "Truncated pcap header"));
+    }
+    let link_type = read_u32(&bytes[20..24]);
+    if link_type != LINKTYPE_DBUS {
+        let reason = format!("Unsupported pcap link type `{}`", link_type);
+        return Err(malformation(20, &reason));
+    }
+
+    let mut offset = PCAP_HEADER_LEN;
+    while offset < bytes.len() {
+        let record_header = bytes
+            .get(offset..offset + PCAP_RECORD_HEADER_LEN)
+            .ok_or_else(|| malformation(offset, "Truncated pcap record header"))?;
+        let captured_len = read_u32(&record_header[8..12]) as usize;
+        let original_len = read_u32(&record_header[12..16]) as usize;
+        if captured_len < original_len {
+            return Err(malformation(
+                offset,
+                "Packet has not been captured entirely",
+            ));
+        }
+        let packet_offset = offset + PCAP_RECORD_HEADER_LEN;
+        let packet = bytes
+            .get(packet_offset..packet_offset + captured_len)
+            .ok_or_else(|| malformation(offset, "Truncated pcap record"))?;
+
+        let mut reader = packet;
+        let message = read_message(&mut reader, packet, packet_offset as u64)?;
+        report.messages.push(message);
+        if !reader.is_empty() {
+            let offset = packet_offset + packet.len() - reader.len();
+            return Err(Malformation {
+                message_offset: packet_offset as u64,
+                ..malformation(offset, "Packet contains data after the message")
+            });
+        }
+        offset = packet_offset + captured_len;
+    }
+    Ok(())
+}
+
+/// Checks a sequence of messages without any framing.
+fn check_raw(bytes: &[u8], report: &mut Report) -> Result<(), Malformation> {
+    let mut reader = bytes;
+    while !reader.is_empty() {
+        let message = read_message(&mut reader, bytes, 0)?;
+        report.messages.push(message);
+    }
+    Ok(())
+}
+
+/// Reads the next message from `reader`, which is the not yet consumed rest of `bytes`.
+/// `offset` is the position of `bytes` within the capture.
+fn read_message(reader: &mut &[u8], bytes: &[u8], offset: u64) -> Result<String, Malformation> {
+    let message_offset = offset + (bytes.len() - reader.len()) as u64;
+    match Message::read_with_options(&mut *reader, ParseOptions::default(), Limits::default()) {
+        Ok(message) => Ok(message.pretty(&PrettyOptions::default())),
+        Err(reason) => Err(Malformation {
+            message_offset,
+            offset: offset + (bytes.len() - reader.len()) as u64,
+            reason,
+        }),
+    }
+}
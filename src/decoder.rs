@@ -0,0 +1,193 @@
+//! Sockets deliver messages in arbitrary chunks, the decoder buffers them
+//! until a complete message has been received.
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use std::io;
+
+use crate::limits::Limits;
+use crate::message::Message;
+use crate::reader::ParseOptions;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::MAX_MESSAGE_SIZE;
+    use crate::message::SIGNAL;
+
+    #[test]
+    fn feed_byte_by_byte() {
+        let mut decoder = MessageDecoder::new();
+        for b in SIGNAL[..SIGNAL.len() - 1].iter() {
+            assert!(decoder.feed(&[*b]).unwrap().is_empty());
+        }
+        assert_eq!(1, decoder.feed(&SIGNAL[SIGNAL.len() - 1..]).unwrap().len());
+        assert!(decoder.buffer.is_empty());
+    }
+
+    #[test]
+    fn feed_multiple_messages() {
+        let mut bytes = SIGNAL.repeat(2);
+        bytes.extend_from_slice(&SIGNAL[..20]);
+
+        let mut decoder = MessageDecoder::new();
+        assert_eq!(2, decoder.feed(&bytes).unwrap().len());
+        assert_eq!(20, decoder.buffer.len());
+        assert_eq!(1, decoder.feed(&SIGNAL[20..]).unwrap().len());
+    }
+
+    #[test]
+    fn feed_returns_messages_preceding_error() {
+        let mut bytes = SIGNAL.repeat(2);
+        // Protocol version of the second message
+        bytes[SIGNAL.len() + 3] = 2;
+
+        let mut decoder = MessageDecoder::new();
+        assert_eq!(1, decoder.feed(&bytes).unwrap().len());
+        let err = decoder.feed(&[]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(decoder.feed(&SIGNAL).is_err());
+    }
+
+    #[test]
+    fn feed_exceeds_limits() {
+        let limits = Limits::with_max_message_size(SIGNAL.len() as u32 - 1);
+        let mut decoder = MessageDecoder::new().with_limits(limits);
+        let err = decoder.feed(&SIGNAL[..16]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn feed_lenient() {
+        // Non-nul padding after the path header field
+        let mut bytes = SIGNAL;
+        bytes[30] = 0xFF;
+
+        let mut decoder = MessageDecoder::new();
+        assert!(decoder.feed(&bytes).is_err());
+
+        let mut decoder = MessageDecoder::with_options(ParseOptions::lenient());
+        assert_eq!(1, decoder.feed(&bytes).unwrap().len());
+    }
+
+    #[test]
+    fn feed_invalid_endianess() {
+        let mut decoder = MessageDecoder::new();
+        let err = decoder.feed(&[b'x'; 16]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn feed_exceeds_max_message_size() {
+        let mut bytes = SIGNAL;
+        bytes[4..8].copy_from_slice(&MAX_MESSAGE_SIZE.to_le_bytes());
+        let mut decoder = MessageDecoder::new();
+        let err = decoder.feed(&bytes).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+}
+
+/// Length of the fixed part of the header including the length of the header field array,
+/// which is enough to compute the length of the whole message.
+const FIXED_HEADER_LENGTH: usize = 16;
+
+/// Incrementally parses messages from the bytes received so far.
+/// Once an error is returned the stream is out of sync and the connection must be closed.
+#[derive(Debug, Default)]
+pub(crate) struct MessageDecoder {
+    /// Received bytes that are not yet part of a complete message.
+    buffer: Vec<u8>,
+    /// How strictly received messages are checked.
+    options: ParseOptions,
+    /// Received messages exceeding these limits are rejected.
+    limits: Limits,
+    /// An error encountered after some messages of the same chunk had been parsed,
+    /// returned by the next call to `feed`.
+    error: Option<io::Error>,
+}
+
+impl MessageDecoder {
+    pub(crate) fn new() -> MessageDecoder {
+        MessageDecoder::default()
+    }
+
+    pub(crate) fn with_options(options: ParseOptions) -> MessageDecoder {
+        MessageDecoder {
+            options,
+            ..MessageDecoder::default()
+        }
+    }
+
+    /// Replaces the limits, e.g. to accept only messages smaller than the specification allows.
+    pub(crate) fn with_limits(mut self, limits: Limits) -> MessageDecoder {
+        self.limits = limits;
+        self
+    }
+
+    /// Appends `bytes` to the buffered input and returns every message that is complete by now.
+    /// If a malformed message follows complete ones, those are returned first and the error
+    /// is returned by the next call.
+    pub(crate) fn feed(&mut self, bytes: &[u8]) -> Result<Vec<Message>, io::Error> {
+        self.buffer.extend_from_slice(bytes);
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        let mut messages = Vec::new();
+        let mut start = 0;
+        let result = loop {
+            let len = match message_length(&self.buffer[start..], self.limits) {
+                Ok(Some(len)) if self.buffer.len() - start >= len => len,
+                Ok(_) => break Ok(()),
+                Err(err) => break Err(err),
+            };
+            let end = start + len;
+            match Message::read_with_options(&self.buffer[start..end], self.options, self.limits) {
+                Ok(message) => messages.push(message),
+                Err(err) => break Err(err),
+            }
+            start = end;
+        };
+        // The malformed message stays buffered, so the stream remains out of sync
+        self.buffer.drain(..start);
+
+        match result {
+            Err(err) if !messages.is_empty() => {
+                self.error = Some(err);
+                Ok(messages)
+            }
+            result => result.map(|()| messages),
+        }
+    }
+}
+
+/// The total length of the message starting at `bytes`, if enough of its header has been received.
+/// Fails as soon as the header announces a message exceeding `limits`, before it is buffered completely.
+fn message_length(bytes: &[u8], limits: Limits) -> Result<Option<usize>, io::Error> {
+    if bytes.len() < FIXED_HEADER_LENGTH {
+        return Ok(None);
+    }
+
+    let (body_length, header_fields_length) = match bytes[0] {
+        b'l' => (
+            LittleEndian::read_u32(&bytes[4..8]),
+            LittleEndian::read_u32(&bytes[12..16]),
+        ),
+        b'B' => (
+            BigEndian::read_u32(&bytes[4..8]),
+            BigEndian::read_u32(&bytes[12..16]),
+        ),
+        x => {
+            let str_err = format!("Invalid endianess flag `{}`", x);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+        }
+    };
+
+    // The header ends after its alignment padding to an 8-boundary.
+    let header_length = (FIXED_HEADER_LENGTH as u64 + u64::from(header_fields_length) + 7) & !7;
+    let message_length = header_length + u64::from(body_length);
+    if message_length > u64::from(limits.max_message_size()) {
+        let str_err = format!("Message size `{}` exceeds maximum", message_length);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+    }
+
+    Ok(Some(message_length as usize))
+}
@@ -0,0 +1,7 @@
+/// Byte order of all multi-byte values in a message, as announced by the
+/// endianness flag in the first byte of the message header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
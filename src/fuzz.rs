@@ -0,0 +1,16 @@
+//! Entry points for the cargo-fuzz targets in `fuzz/`, which can only call public functions.
+use crate::limits::Limits;
+use crate::message::Message;
+use crate::reader::ParseOptions;
+
+/// Parses `data` as a message, strictly and leniently, and marshals whatever was accepted.
+/// Malformed input must result in an error, never in a panic or an unbounded allocation.
+#[inline]
+pub fn message_read(data: &[u8]) {
+    let _ = Message::read(data);
+    if let Ok(message) =
+        Message::read_with_options(data, ParseOptions::lenient(), Limits::default())
+    {
+        let _ = message.to_writer(Vec::new());
+    }
+}
@@ -0,0 +1,202 @@
+//! Random values for a given signature, used to stress the marshaling code with
+//! structurally interesting input. Mutated marshaled bodies exercise the read path
+//! with input that is almost, but not quite, valid. Available to other crates with the
+//! `generator` feature, e.g. for property tests of code built on top of this crate.
+use quickcheck::{Arbitrary, Gen};
+
+use crate::limits::MAX_SIGNATURE_LENGTH;
+use crate::type_system::{skip_complete_type, ObjectPath, Signature, UnixFd, Value, Variant};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endianness::Endianness;
+    use crate::reader::DbusReader;
+    use crate::writer::DbusWriter;
+
+    /// Marshals each argument with its complete type from the signature.
+    fn marshal(body: &ArbitraryBody) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        let mut writer = DbusWriter::new(&mut buffer, Endianness::Little);
+        for (arg, complete_type) in body.args.iter().zip(body.signature.complete_types()) {
+            arg.write_with_signature(&mut writer, &Signature(complete_type.to_string().into()))
+                .unwrap();
+        }
+        buffer
+    }
+
+    quickcheck! {
+        fn generated_signatures_are_valid(body: ArbitraryBody) -> bool {
+            body.signature.validate().is_ok()
+        }
+
+        fn generated_bodies_round_trip(body: ArbitraryBody) -> bool {
+            let buffer = marshal(&body);
+            let mut reader = DbusReader::new(buffer.as_slice(), Endianness::Little);
+            Value::read_all(&mut reader, &body.signature).unwrap() == body.args
+                && reader.position() == buffer.len() as u64
+        }
+
+        fn mutated_bodies_never_panic(body: ArbitraryBody, mutations: Vec<(usize, u8)>) -> bool {
+            let mut buffer = marshal(&body);
+            mutate(&mut buffer, &mutations);
+            let mut reader = DbusReader::new(buffer.as_slice(), Endianness::Little);
+            let _ = Value::read_all(&mut reader, &body.signature);
+            true
+        }
+    }
+}
+
+/// Containers are nested at most this deep, which keeps generated values small.
+const MAX_DEPTH: usize = 4;
+
+/// Arrays hold at most this many elements.
+const MAX_ELEMENTS: usize = 4;
+
+/// A body signature along with random arguments matching it.
+#[derive(Clone, Debug)]
+pub struct ArbitraryBody {
+    pub signature: Signature,
+    pub args: Vec<Value>,
+}
+
+impl Arbitrary for ArbitraryBody {
+    #[inline]
+    fn arbitrary<G: Gen>(g: &mut G) -> ArbitraryBody {
+        let signature = arbitrary_signature(g);
+        let args = arbitrary_body(g, &signature);
+        ArbitraryBody { signature, args }
+    }
+}
+
+/// A random index below `n`.
+fn choose<G: Gen>(g: &mut G, n: usize) -> usize {
+    (u32::arbitrary(g) as usize) % n
+}
+
+/// A random valid signature made up of up to four complete types.
+#[inline]
+pub fn arbitrary_signature<G: Gen>(g: &mut G) -> Signature {
+    let mut signature = String::new();
+    for _ in 0..choose(g, 5) {
+        let complete_type = arbitrary_complete_type(g, MAX_DEPTH);
+        if signature.len() + complete_type.len() > MAX_SIGNATURE_LENGTH {
+            break;
+        }
+        signature.push_str(&complete_type);
+    }
+    Signature(signature.into())
+}
+
+/// A random single complete type with containers nested at most `depth` deep.
+fn arbitrary_complete_type<G: Gen>(g: &mut G, depth: usize) -> String {
+    const BASIC_TYPES: &[u8] = b"ybnqiuxtdhsog";
+    let containers = if depth == 0 { 0 } else { 4 };
+    let choice = choose(g, BASIC_TYPES.len() + containers);
+    if choice < BASIC_TYPES.len() {
+        return char::from(BASIC_TYPES[choice]).to_string();
+    }
+
+    match choice - BASIC_TYPES.len() {
+        0 => "v".to_string(),
+        1 => format!("a{}", arbitrary_complete_type(g, depth - 1)),
+        2 => {
+            let mut struct_type = "(".to_string();
+            for _ in 0..=choose(g, 3) {
+                struct_type.push_str(&arbitrary_complete_type(g, depth - 1));
+            }
+            struct_type.push(')');
+            struct_type
+        }
+        _ => {
+            let key = char::from(BASIC_TYPES[choose(g, BASIC_TYPES.len())]);
+            format!("a{{{}{}}}", key, arbitrary_complete_type(g, depth - 1))
+        }
+    }
+}
+
+/// Random arguments for each complete type of a valid `signature`.
+#[inline]
+pub fn arbitrary_body<G: Gen>(g: &mut G, signature: &Signature) -> Vec<Value> {
+    signature
+        .complete_types()
+        .map(|complete_type| arbitrary_value(g, complete_type.as_bytes(), MAX_DEPTH).0)
+        .collect()
+}
+
+/// A random value of the first complete type in `types`, returning it along with the remaining types.
+/// Containers are nested at most `depth` deep, including the types chosen for variants.
+fn arbitrary_value<'a, G: Gen>(g: &mut G, types: &'a [u8], depth: usize) -> (Value, &'a [u8]) {
+    let (type_code, rest) = types.split_first().expect("valid signature");
+    let value = match type_code {
+        b'y' => Value::Byte(u8::arbitrary(g)),
+        b'b' => Value::Boolean(bool::arbitrary(g)),
+        b'n' => Value::Int16(i16::arbitrary(g)),
+        b'q' => Value::Uint16(u16::arbitrary(g)),
+        b'i' => Value::Int32(i32::arbitrary(g)),
+        b'u' => Value::Uint32(u32::arbitrary(g)),
+        b'x' => Value::Int64(i64::arbitrary(g)),
+        b't' => Value::Uint64(u64::arbitrary(g)),
+        // NaN would not compare equal after a round trip
+        b'd' => Value::Double(f64::from(i32::arbitrary(g)) / 8.0),
+        b'h' => Value::UnixFd(UnixFd(u32::arbitrary(g))),
+        b's' => Value::String(String::arbitrary(g).replace('\0', "")),
+        b'o' => Value::ObjectPath(arbitrary_object_path(g)),
+        b'g' => Value::Signature(arbitrary_signature(g)),
+        b'v' => {
+            let depth = depth.saturating_sub(1);
+            let complete_type = arbitrary_complete_type(g, depth);
+            let value = arbitrary_value(g, complete_type.as_bytes(), depth).0;
+            Value::from(Variant::from_parts(Signature(complete_type.into()), value))
+        }
+        b'a' => {
+            let rest_array = skip_complete_type(rest).expect("valid signature");
+            if rest.first() == Some(&b'y') {
+                return (Value::ByteArray(Vec::arbitrary(g)), rest_array);
+            }
+            let mut elements = Vec::new();
+            for _ in 0..choose(g, MAX_ELEMENTS + 1) {
+                elements.push(arbitrary_value(g, rest, depth.saturating_sub(1)).0);
+            }
+            return (Value::Array(elements), rest_array);
+        }
+        b'(' => {
+            let mut fields = Vec::new();
+            let mut rest_struct = rest;
+            while rest_struct.first() != Some(&b')') {
+                let (field, rest_field) = arbitrary_value(g, rest_struct, depth.saturating_sub(1));
+                fields.push(field);
+                rest_struct = rest_field;
+            }
+            return (Value::Struct(fields), &rest_struct[1..]);
+        }
+        b'{' => {
+            let (k, rest_key) = arbitrary_value(g, rest, depth.saturating_sub(1));
+            let (v, rest_value) = arbitrary_value(g, rest_key, depth.saturating_sub(1));
+            return (Value::DictEntry(Box::new(k), Box::new(v)), &rest_value[1..]);
+        }
+        x => panic!("Invalid type code `{}` in signature", char::from(*x)),
+    };
+    (value, rest)
+}
+
+/// A random object path of up to three elements.
+fn arbitrary_object_path<G: Gen>(g: &mut G) -> ObjectPath {
+    let elements: Vec<String> = (0..choose(g, 4))
+        .map(|i| format!("e{}_{}", i, u8::arbitrary(g)))
+        .collect();
+    ObjectPath(format!("/{}", elements.join("/")))
+}
+
+/// Overwrites bytes of `bytes` as described by `mutations`, pairs of position and new value.
+/// Positions wrap around, so any mutation applies to non-empty input.
+#[inline]
+pub fn mutate(bytes: &mut [u8], mutations: &[(usize, u8)]) {
+    if bytes.is_empty() {
+        return;
+    }
+    let len = bytes.len();
+    for (position, value) in mutations {
+        bytes[position % len] = *value;
+    }
+}
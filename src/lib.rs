@@ -11,8 +11,19 @@ extern crate lazy_static;
 extern crate quickcheck;
 
 mod address;
+mod archive;
+#[cfg(feature = "check")]
+pub mod check;
+mod decoder;
+mod endianness;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+#[cfg(any(test, feature = "generator"))]
+pub mod generator;
+mod limits;
 mod message;
 mod names;
+mod pretty;
 mod reader;
 mod type_system;
 mod writer;
@@ -0,0 +1,60 @@
+//! Limits imposed by the D-Bus specification, all validation in this crate refers to these.
+//! https://dbus.freedesktop.org/doc/dbus-specification.html#message-protocol-marshaling
+
+/// There is a maximum name length of 255 which applies to bus names, interfaces, and members.
+pub const MAX_NAME_LENGTH: usize = 255;
+
+/// The maximum length of a message, including header, header alignment padding,
+/// and body is 2 to the 27th power or 134217728 (128 MiB).
+/// Implementations must not send or accept messages exceeding this size.
+pub const MAX_MESSAGE_SIZE: u32 = 1 << 27;
+
+/// Arrays have a maximum length defined to be 2 to the 26th power or 67108864 (64 MiB).
+pub const MAX_ARRAY_LENGTH: u32 = 1 << 26;
+
+/// Signatures have a maximum length of 255, their length is marshaled as a single byte.
+pub const MAX_SIGNATURE_LENGTH: usize = 255;
+
+/// Arrays may be nested at most 32 deep, i.e. a value may be enclosed by at most
+/// 32 arrays, whether directly as in `aay` or with other containers in between.
+pub const MAX_ARRAY_NESTING_DEPTH: usize = 32;
+
+/// Structs may be nested at most 32 deep, i.e. at most 32 open parentheses may be
+/// unclosed at any point of a signature. Dict entries count as structs.
+pub const MAX_STRUCT_NESTING_DEPTH: usize = 32;
+
+/// The maximum total depth of container nesting, arrays and structs combined,
+/// including nesting through variants.
+pub const MAX_SIGNATURE_DEPTH: usize = MAX_ARRAY_NESTING_DEPTH + MAX_STRUCT_NESTING_DEPTH;
+
+/// The maximum number of unix file descriptors accompanying a message, as in the reference implementation.
+pub const MAX_MESSAGE_UNIX_FDS: u32 = MAX_MESSAGE_SIZE / 4;
+
+/// Limits enforced in addition to those of the specification, e.g. by embedders
+/// that want to bound the memory a single message may take.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    max_message_size: u32,
+}
+
+impl Limits {
+    /// Messages are limited to `max_message_size` bytes, which can only be lower than `MAX_MESSAGE_SIZE`.
+    pub fn with_max_message_size(max_message_size: u32) -> Limits {
+        Limits {
+            max_message_size: max_message_size.min(MAX_MESSAGE_SIZE),
+        }
+    }
+
+    /// The maximum length of a message including header, header alignment padding, and body.
+    pub fn max_message_size(&self) -> u32 {
+        self.max_message_size
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_message_size: MAX_MESSAGE_SIZE,
+        }
+    }
+}
@@ -1,18 +1,54 @@
 //! https://dbus.freedesktop.org/doc/dbus-specification.html#message-protocol-marshaling
-use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+#![deny(
+    clippy::arithmetic_side_effects,
+    clippy::expect_used,
+    clippy::indexing_slicing,
+    clippy::panic,
+    clippy::unwrap_used
+)]
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use std::convert::TryFrom;
+use std::fmt;
 use std::io;
+use std::str::FromStr;
 
+use crate::endianness::Endianness;
+use crate::limits::{Limits, MAX_ARRAY_LENGTH, MAX_MESSAGE_SIZE, MAX_MESSAGE_UNIX_FDS};
 use crate::names::{BusName, ErrorName, InterfaceName, MemberName};
-use crate::reader::{DbusRead, DbusReader};
-use crate::type_system::{ObjectPath, Serial, Signature, UnixFd};
+use crate::pretty::PrettyOptions;
+use crate::reader::{DbusRead, DbusReader, ParseOptions};
+use crate::type_system::{
+    skip_complete_type, ContainerDepth, ObjectPath, Serial, Signature, UnixFd, Value, ValueError,
+    Variant,
+};
 use crate::writer::{DbusWrite, DbusWriter};
 
+/// Signal with a string argument as marshaled by libdbus, used by the tests of several modules.
+#[cfg(test)]
+pub(crate) const SIGNAL: [u8; 106] = [
+    108, 4, 0, 1, 10, 0, 0, 0, 1, 0, 0, 0, 79, 0, 0, 0, 1, 1, 111, 0, 5, 0, 0, 0, 47, 112, 97, 116,
+    104, 0, 0, 0, 2, 1, 115, 0, 24, 0, 0, 0, 99, 111, 109, 46, 101, 120, 97, 109, 112, 108, 101,
+    46, 77, 117, 115, 105, 99, 80, 108, 97, 121, 101, 114, 49, 0, 0, 0, 0, 0, 0, 0, 0, 3, 1, 115,
+    0, 6, 0, 0, 0, 109, 101, 109, 98, 101, 114, 0, 0, 8, 1, 103, 0, 1, 115, 0, 0, 5, 0, 0, 0, 104,
+    101, 108, 108, 111, 0,
+];
+
 #[cfg(test)]
+#[allow(
+    clippy::expect_used,
+    clippy::indexing_slicing,
+    clippy::panic,
+    clippy::unwrap_used
+)]
 mod tests {
+    use crate::generator::mutate;
     use crate::message::*;
     use libdbus_sys;
+    use std::convert::TryFrom;
     use std::ffi::CString;
     use std::io::BufWriter;
+    use std::str::FromStr;
 
     fn reverse<T: Clone>(xs: &[T]) -> Vec<T> {
         let mut rev = vec![];
@@ -26,6 +62,14 @@ mod tests {
         fn prop(xs: Vec<u32>) -> bool {
             xs == reverse(&reverse(&xs))
         }
+
+        fn read_mutated_never_panics(mutations: Vec<(usize, u8)>) -> bool {
+            let mut bytes = SIGNAL.to_vec();
+            mutate(&mut bytes, &mutations);
+            let _ = Message::read(bytes.as_slice());
+            let _ = Message::read_with_options(bytes.as_slice(), ParseOptions::lenient(), Limits::default());
+            true
+        }
     }
 
     fn create_libdbus_signal() -> Vec<u8> {
@@ -67,78 +111,1362 @@ mod tests {
         }
     }
 
-    #[test]
-    fn compare_marshalled_signal_with_libdbus() {
-        // dbus_message_marshal(msg: *mut DBusMessage, marshalled_data_p: *mut *mut c_char, len_p: *mut c_int) -> u32;
+    #[test]
+    fn compare_marshalled_signal_with_libdbus() {
+        // dbus_message_marshal(msg: *mut DBusMessage, marshalled_data_p: *mut *mut c_char, len_p: *mut c_int) -> u32;
+
+        let v = create_libdbus_signal();
+        println!("DBUS Message Length(): {:X?}", v);
+
+        let header = Header {
+            endianess_flag: EndianessFlag::LittleEndian,
+            message_type: MessageType::Signal,
+            flags: HeaderFlags::NO_REPLY_EXPECTED,
+            major_protocol_version: MajorProtocolVersion(1),
+            length_message_body: 0,
+            serial: Serial::FIRST,
+            header_fields: signal_fields(),
+        };
+
+        let body = Body::default();
+
+        let m = Message {
+            header,
+            body,
+            raw_header: None,
+        };
+
+        let mut buffer = Vec::with_capacity(1024);
+        let len = m.write(&mut buffer).unwrap();
+        println!("DBUS Message Length({}): {:X?}", len, buffer);
+
+        assert_eq!(true, true);
+    }
+
+    /// The header fields required for a signal
+    fn signal_fields() -> HeaderFields {
+        let mut header_fields = HeaderFields::default();
+        header_fields.set_path(Some(ObjectPath("/path".to_string())));
+        header_fields.set_interface(Some(
+            InterfaceName::from_str("com.example.MusicPlayer1").unwrap(),
+        ));
+        header_fields.set_member(Some(MemberName::from_str("member").unwrap()));
+        header_fields
+    }
+
+    fn signal_header(header_fields: HeaderFields) -> Header {
+        Header {
+            endianess_flag: EndianessFlag::LittleEndian,
+            message_type: MessageType::Signal,
+            flags: HeaderFlags::NO_REPLY_EXPECTED,
+            major_protocol_version: MajorProtocolVersion(1),
+            length_message_body: 0,
+            serial: Serial::FIRST,
+            header_fields,
+        }
+    }
+
+    #[test]
+    fn endianess_flag_default() {
+        let native = if cfg!(target_endian = "little") {
+            EndianessFlag::LittleEndian
+        } else {
+            EndianessFlag::BigEndian
+        };
+        assert_eq!(native, EndianessFlag::default());
+    }
+
+    #[test]
+    fn header_flags() {
+        let mut header = signal_header(signal_fields());
+        header.flags = HeaderFlags::from_bits_preserve(0x80 | 0x2);
+
+        let mut m = Message {
+            header,
+            body: Body::default(),
+            raw_header: None,
+        };
+        assert!(m.no_auto_start());
+        assert!(!m.no_reply_expected());
+
+        m.set_no_auto_start(false);
+        m.set_allow_interactive_authorization(true);
+        assert_eq!(0x80 | 0x4, m.header.flags.bits());
+
+        let mut buffer = Vec::new();
+        m.write(&mut buffer).unwrap();
+        assert_eq!(0x80 | 0x4, buffer[2]);
+    }
+
+    #[test]
+    fn read_signal() {
+        let m = Message::read(&SIGNAL[..]).unwrap();
+        assert_eq!(EndianessFlag::LittleEndian, m.header.endianess_flag);
+        assert_eq!(MessageType::Signal, m.header.message_type);
+        assert_eq!(Serial::FIRST, m.header.serial);
+        assert_eq!(10, m.header.length_message_body);
+
+        assert_eq!(Some(&ObjectPath("/path".to_string())), m.path());
+        assert_eq!(
+            Some(&InterfaceName::from_str("com.example.MusicPlayer1").unwrap()),
+            m.interface()
+        );
+        assert_eq!(Some(&MemberName::from_str("member").unwrap()), m.member());
+        assert_eq!(Some(&Signature("s".into())), m.signature());
+        assert_eq!(None, m.error_name());
+        assert_eq!(None, m.reply_serial());
+        assert_eq!(None, m.destination());
+        assert_eq!(None, m.sender());
+        assert_eq!(None, m.unix_fds());
+        assert_eq!(Some(&SIGNAL[96..]), m.body.raw.as_deref());
+        assert_eq!(vec![Value::from("hello")], m.body.args);
+        assert_eq!(Some(&SIGNAL[..96]), m.header_bytes());
+        assert_eq!(Some(&SIGNAL[96..]), m.body_bytes());
+        assert_eq!("string \"hello\"", m.body.pretty(&PrettyOptions::default()));
+    }
+
+    #[test]
+    fn body_debug() {
+        let body = Body {
+            args: vec![Value::from(vec![0u8; 1 << 20])],
+            raw: Some(vec![0; 1 << 20]),
+        };
+        let debug = format!("{:?}", body);
+        assert!(debug.len() < 512);
+        assert!(debug.ends_with("raw: Some(1048576) }"));
+    }
+
+    #[test]
+    fn pretty_message() {
+        let m = Message::read(&SIGNAL[..]).unwrap();
+        assert_eq!(
+            "signal serial=1 path=/path interface=com.example.MusicPlayer1 member=member\n   string \"hello\"",
+            m.pretty(&PrettyOptions::default())
+        );
+    }
+
+    #[test]
+    fn write_signal() {
+        let m = Message::read(&SIGNAL[..]).unwrap();
+        let mut buffer = Vec::new();
+        assert_eq!(SIGNAL.len() as u64, m.write(&mut buffer).unwrap());
+        assert_eq!(&SIGNAL[..], buffer.as_slice());
+
+        let mut header_fields = HeaderFields::default();
+        header_fields.set_path(Some(ObjectPath("/".to_string())));
+        header_fields.set_unix_fds(Some(2));
+        let mut buffer = Vec::new();
+        let mut writer = DbusWriter::new(&mut buffer, Endianness::Little);
+        signal_header(header_fields).write(&mut writer).unwrap();
+        assert_eq!(
+            vec![
+                108, 4, 1, 1, 0, 0, 0, 0, 1, 0, 0, 0, 24, 0, 0, 0, 1, 1, b'o', 0, 1, 0, 0, 0, b'/',
+                0, 0, 0, 0, 0, 0, 0, 9, 1, b'u', 0, 2, 0, 0, 0
+            ],
+            buffer[..40].to_vec()
+        );
+        assert_eq!(40, buffer.len());
+    }
+
+    #[test]
+    fn write_computes_body_length() {
+        let mut header_fields = signal_fields();
+        header_fields.set_signature(Some(Signature("su".into())));
+        let m = Message {
+            header: signal_header(header_fields),
+            body: Body {
+                args: vec![Value::from("hello"), Value::from(7u32)],
+                raw: None,
+            },
+            raw_header: None,
+        };
+        assert_eq!(0, m.header.length_message_body);
+
+        let mut buffer = Vec::new();
+        m.write(&mut buffer).unwrap();
+        let parsed = Message::read(buffer.as_slice()).unwrap();
+        assert_eq!(16, parsed.header.length_message_body);
+        assert_eq!(m.body.args, parsed.body.args);
+    }
+
+    #[test]
+    fn message_size_limits() {
+        let m = Message::read(&SIGNAL[..]).unwrap();
+        let limits = Limits::with_max_message_size(SIGNAL.len() as u32);
+        let mut buffer = Vec::new();
+        assert_eq!(
+            SIGNAL.len() as u64,
+            m.write_with_limits(&mut buffer, limits).unwrap()
+        );
+        assert!(Message::read_with_options(&SIGNAL[..], ParseOptions::default(), limits).is_ok());
+
+        let limits = Limits::with_max_message_size(SIGNAL.len() as u32 - 1);
+        let mut buffer = Vec::new();
+        let err = m.write_with_limits(&mut buffer, limits).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        assert!(buffer.is_empty());
+        let err =
+            Message::read_with_options(&SIGNAL[..], ParseOptions::default(), limits).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        // The header field array length alone exceeds the limit, which is detected before
+        // the fields, here missing entirely, are read.
+        let limits = Limits::with_max_message_size(4096);
+        let mut header = SIGNAL[..16].to_vec();
+        header[12..16].copy_from_slice(&8192u32.to_le_bytes());
+        let err = Message::read_with_options(header.as_slice(), ParseOptions::default(), limits)
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(err.to_string().contains("exceeds maximum"));
+    }
+
+    #[test]
+    fn read_truncated_message() {
+        for len in 0..SIGNAL.len() {
+            assert!(Message::read(&SIGNAL[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn body_check_against() {
+        let m = Message::read(&SIGNAL[..]).unwrap();
+        let endianess_flag = m.header.endianess_flag;
+        let check = |signature: &str| {
+            m.body
+                .check_against(endianess_flag, &Signature(signature.to_string().into()))
+        };
+        assert!(check("s").is_ok());
+        assert!(check("").is_err());
+        assert!(check("ss").is_err());
+        assert!(check("u").is_err());
+        assert!(check("ay").is_err());
+
+        // Array of two structs, each an int16 followed by a variant holding a byte.
+        let body = Body {
+            args: Vec::new(),
+            raw: Some(vec![
+                14, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1, b'y', 0, 7, 0, 0, 2, 0, 1, b'y', 0, 8,
+            ]),
+        };
+        let signature = Signature("a(nv)".into());
+        assert!(body.check_against(endianess_flag, &signature).is_ok());
+        let signature = Signature("a(qv)y".into());
+        assert!(body.check_against(endianess_flag, &signature).is_err());
+        assert!(Body::default()
+            .check_against(endianess_flag, &Signature("".into()))
+            .is_ok());
+
+        // Invalid signatures are rejected before looking at the body
+        for signature in ["()", "{yy}", "a{vy}", "(y"].iter() {
+            let err = Body::default()
+                .check_against(endianess_flag, &Signature(signature.to_string().into()))
+                .unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+    }
+
+    #[test]
+    fn set_body_raw() {
+        let mut m = Message {
+            header: signal_header(signal_fields()),
+            body: Body::default(),
+            raw_header: None,
+        };
+        let raw = SIGNAL[96..].to_vec();
+        assert!(m
+            .set_body_raw(Signature("u".into()), raw.clone(), 0)
+            .is_err());
+        m.set_body_raw(Signature("s".into()), raw.clone(), 0)
+            .unwrap();
+
+        assert_eq!(10, m.header.length_message_body);
+        assert_eq!(
+            Some(&Signature("s".into())),
+            m.header.header_fields.signature()
+        );
+        assert_eq!(None, m.header.header_fields.unix_fds());
+
+        let mut buffer = Vec::new();
+        m.write(&mut buffer).unwrap();
+        assert!(buffer.ends_with(&raw));
+    }
+
+    #[test]
+    fn convert_endianness() {
+        let m = Message::read(&SIGNAL[..]).unwrap();
+        let converted = m.convert_endianness(EndianessFlag::BigEndian).unwrap();
+        assert_eq!(EndianessFlag::BigEndian, converted.header.endianess_flag);
+        assert_eq!(m.header.header_fields, converted.header.header_fields);
+        assert_eq!(m.body.args, converted.body.args);
+        assert_eq!(None, converted.header_bytes());
+        assert_eq!(
+            vec![0, 0, 0, 5, b'h', b'e', b'l', b'l', b'o', 0],
+            converted
+                .body
+                .marshal(EndianessFlag::BigEndian, converted.signature())
+                .unwrap()
+        );
+
+        let mut raw = Message {
+            header: signal_header(HeaderFields::default()),
+            body: Body::default(),
+            raw_header: None,
+        };
+        raw.set_body_raw(Signature("q".into()), vec![1, 2], 0)
+            .unwrap();
+        let converted = raw.convert_endianness(EndianessFlag::BigEndian).unwrap();
+        assert_eq!(raw.body.args, converted.body.args);
+        assert_eq!(
+            vec![2, 1],
+            converted
+                .body
+                .marshal(EndianessFlag::BigEndian, converted.signature())
+                .unwrap()
+        );
+
+        // Empty arrays, as in most `PropertiesChanged` signals, survive the conversion
+        let mut buffer = Vec::new();
+        properties_changed().write(&mut buffer).unwrap();
+        let m = Message::read(buffer.as_slice()).unwrap();
+        let converted = m.convert_endianness(EndianessFlag::BigEndian).unwrap();
+        assert_eq!(m.header.header_fields, converted.header.header_fields);
+        assert_eq!(m.body.args, converted.body.args);
+        let mut buffer = Vec::new();
+        converted.write(&mut buffer).unwrap();
+        let read = Message::read(buffer.as_slice()).unwrap();
+        assert_eq!(EndianessFlag::BigEndian, read.header.endianess_flag);
+        assert_eq!(m.body.args, read.body.args);
+    }
+
+    #[test]
+    fn convert_endianness_keeps_body_bytes() {
+        // Invalid UTF-8 in the string argument, only accepted by lenient parsing
+        let mut bytes = SIGNAL.to_vec();
+        bytes[100..102].copy_from_slice(&[0xC3, 0x28]);
+        let m = Message::read_with_options(&bytes[..], ParseOptions::lenient(), Limits::default())
+            .unwrap();
+
+        let big = m.convert_endianness(EndianessFlag::BigEndian).unwrap();
+        assert_eq!(
+            Some(&[0, 0, 0, 5, 0xC3, 0x28, b'l', b'l', b'o', 0][..]),
+            big.body_bytes()
+        );
+        let mut buffer = Vec::new();
+        big.to_writer(&mut buffer).unwrap();
+        let read = Message::read_with_options(
+            buffer.as_slice(),
+            ParseOptions::lenient(),
+            Limits::default(),
+        )
+        .unwrap();
+        assert_eq!(big.body_bytes(), read.body_bytes());
+
+        let little = read
+            .convert_endianness(EndianessFlag::LittleEndian)
+            .unwrap();
+        let big = little.convert_endianness(EndianessFlag::BigEndian).unwrap();
+        assert_eq!(Some(&bytes[96..]), little.body_bytes());
+        assert_eq!(read.body_bytes(), big.body_bytes());
+
+        // Containers and variants are swapped element by element
+        let mut buffer = Vec::new();
+        properties_changed().write(&mut buffer).unwrap();
+        let m = Message::read(buffer.as_slice()).unwrap();
+        let big = m.convert_endianness(EndianessFlag::BigEndian).unwrap();
+        let little = big.convert_endianness(EndianessFlag::LittleEndian).unwrap();
+        let big_again = little.convert_endianness(EndianessFlag::BigEndian).unwrap();
+        assert_eq!(m.body_bytes(), little.body_bytes());
+        assert_eq!(big.body_bytes(), big_again.body_bytes());
+        let mut buffer = Vec::new();
+        big.to_writer(&mut buffer).unwrap();
+        assert_eq!(
+            m.body.args,
+            Message::read(buffer.as_slice()).unwrap().body.args
+        );
+    }
+
+    /// A `PropertiesChanged` signal invalidating one property, as the bus sends them.
+    fn properties_changed() -> Message {
+        let mut fields = signal_fields();
+        fields.set_signature(Some(Signature("sa{sv}asv".into())));
+        let body = Body {
+            args: vec![
+                Value::from("com.example.MusicPlayer1"),
+                Value::Array(Vec::new()),
+                Value::Array(Vec::new()),
+                Value::from(Variant::of(Vec::<String>::new())),
+            ],
+            raw: None,
+        };
+        Message {
+            header: signal_header(fields),
+            body,
+            raw_header: None,
+        }
+    }
+
+    #[test]
+    fn write_empty_arrays() {
+        let m = properties_changed();
+        let mut buffer = Vec::new();
+        m.write(&mut buffer).unwrap();
+        let read = Message::read(buffer.as_slice()).unwrap();
+        assert_eq!(m.body.args, read.body.args);
+
+        // The arguments must match the signature
+        let mut m = properties_changed();
+        m.body.args.pop();
+        let err = m.write(&mut Vec::new()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    }
+
+    #[test]
+    fn reply_to_call() {
+        let mut header_fields = HeaderFields::default();
+        let sender = BusName::from_str(":1.42").unwrap();
+        let destination = BusName::from_str("com.example.MusicPlayer1").unwrap();
+        header_fields.set_sender(Some(sender.clone()));
+        header_fields.set_destination(Some(destination.clone()));
+
+        let mut header = signal_header(header_fields);
+        header.message_type = MessageType::MethodCall;
+        header.endianess_flag = EndianessFlag::BigEndian;
+        header.serial = Serial::try_from(7).unwrap();
+        let call = Message {
+            header,
+            body: Body::default(),
+            raw_header: None,
+        };
+
+        let serial = Serial::FIRST;
+        let reply = Message::method_return(&call, serial);
+        assert_eq!(MessageType::MethodReturn, reply.header.message_type);
+        assert_eq!(EndianessFlag::BigEndian, reply.header.endianess_flag);
+        assert_eq!(serial, reply.header.serial);
+
+        let reply_fields = &reply.header.header_fields;
+        assert_eq!(Some(call.header.serial), reply_fields.reply_serial());
+        assert_eq!(Some(&sender), reply_fields.destination());
+        assert_eq!(Some(&destination), reply_fields.sender());
+
+        let error_name = ErrorName::from_str("com.example.Error.Failed").unwrap();
+        let error = Message::error(&call, serial, error_name.clone(), "failed").unwrap();
+        assert_eq!(MessageType::Error, error.header.message_type);
+        assert_eq!(Some(&error_name), error.header.header_fields.error_name());
+        assert_eq!(vec![Value::from("failed")], error.body.args);
+        assert_ne!(0, error.header.length_message_body);
+    }
+
+    #[test]
+    fn constructors() {
+        let path = ObjectPath("/path".to_string());
+        let interface = InterfaceName::from_str("com.example.MusicPlayer1").unwrap();
+        let member = MemberName::from_str("Play").unwrap();
+
+        let destination = BusName::from_str("com.example.MusicPlayer1").unwrap();
+        let call = Message::method_call(
+            Some(destination.clone()),
+            path.clone(),
+            None,
+            member.clone(),
+            Serial::FIRST,
+        );
+        let fields = &call.header.header_fields;
+        assert_eq!(MessageType::MethodCall, call.header.message_type);
+        assert!(!call.no_reply_expected());
+        assert_eq!(Some(&destination), fields.destination());
+        assert_eq!(Some(&path), fields.path());
+        assert_eq!(None, fields.interface());
+        assert_eq!(Some(&member), fields.member());
+
+        let signal = Message::signal(path, interface.clone(), member, Serial::FIRST);
+        assert_eq!(MessageType::Signal, signal.header.message_type);
+        assert!(signal.no_reply_expected());
+        assert_eq!(Some(&interface), signal.header.header_fields.interface());
+    }
+
+    #[test]
+    fn message_builder() {
+        let m = MessageBuilder::new(MessageType::MethodCall)
+            .destination(BusName::from_str("com.example.MusicPlayer1").unwrap())
+            .path(ObjectPath("/path".to_string()))
+            .interface(InterfaceName::from_str("com.example.MusicPlayer1").unwrap())
+            .member(MemberName::from_str("Play").unwrap())
+            .with_flags(HeaderFlags::NO_AUTO_START)
+            .append_arg("track")
+            .append_arg(3u32)
+            .build(Serial::FIRST)
+            .unwrap();
+        assert_eq!(MessageType::MethodCall, m.header.message_type);
+        assert!(m.no_auto_start());
+        assert_eq!(
+            Some(&Signature("su".into())),
+            m.header.header_fields.signature()
+        );
+
+        let mut buffer = Vec::new();
+        m.write(&mut buffer).unwrap();
+        let parsed = Message::read(buffer.as_slice()).unwrap();
+        assert_eq!(m.header.header_fields, parsed.header.header_fields);
+        assert_eq!(m.body.args, parsed.body.args);
+
+        let err = MessageBuilder::new(MessageType::Signal)
+            .path(ObjectPath("/path".to_string()))
+            .build(Serial::FIRST)
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        assert!(err.to_string().ends_with("Interface, Member"));
+
+        let m = MessageBuilder::new(MessageType::MethodReturn)
+            .reply_serial(Serial::FIRST)
+            .build(Serial::FIRST)
+            .unwrap();
+        assert_eq!(None, m.header.header_fields.signature());
+    }
+
+    #[test]
+    fn header_validate() {
+        assert_eq!(Ok(()), signal_header(signal_fields()).validate());
+
+        let mut header = signal_header(HeaderFields::default());
+        header.message_type = MessageType::Error;
+        header
+            .header_fields
+            .set_error_name(Some(ErrorName::from_str("com.example.Error").unwrap()));
+        assert_eq!(
+            Err(HeaderError::MissingFields(
+                MessageType::Error,
+                vec![HeaderFieldCode::ReplySerial]
+            )),
+            header.validate()
+        );
+
+        // A signal without interface must be rejected on send and receive
+        let mut fields = signal_fields();
+        fields.set_interface(None);
+        let mut m = Message {
+            header: signal_header(fields),
+            body: Body::default(),
+            raw_header: None,
+        };
+        let err = m.write(&mut Vec::new()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+
+        m.header.header_fields.set_interface(Some(
+            InterfaceName::from_str("com.example.MusicPlayer1").unwrap(),
+        ));
+        let mut buffer = Vec::new();
+        m.write(&mut buffer).unwrap();
+        // Drop the interface field from the marshaled header
+        let start = 16 + 16;
+        let end = start + 40;
+        assert_eq!(HeaderFieldCode::Interface as u8, buffer[start]);
+        buffer.drain(start..end);
+        buffer[12] -= 40;
+        let err = Message::read(buffer.as_slice()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!(
+            Some(&HeaderError::MissingFields(
+                MessageType::Signal,
+                vec![HeaderFieldCode::Interface]
+            )),
+            err.get_ref().and_then(|e| e.downcast_ref())
+        );
+    }
+
+    #[test]
+    fn reserved_interface() {
+        let mut fields = signal_fields();
+        let local = InterfaceName::from_str("org.freedesktop.DBus.Local").unwrap();
+        fields.set_interface(Some(local));
+        let header = signal_header(fields);
+        assert_eq!(Ok(()), header.validate());
+        assert_eq!(
+            Err(HeaderError::ReservedInterface),
+            header.validate_outgoing()
+        );
+
+        let m = Message {
+            header,
+            body: Body::default(),
+            raw_header: None,
+        };
+        let err = m.write(&mut Vec::new()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+
+        // Received messages on the reserved interface can still be stored
+        let mut buffer = Vec::new();
+        m.to_writer(&mut buffer).unwrap();
+        let parsed = Message::from_reader(buffer.as_slice()).unwrap();
+        let mut stored = Vec::new();
+        parsed.to_writer(&mut stored).unwrap();
+        assert_eq!(buffer, stored);
+    }
+
+    #[test]
+    fn to_writer_writes_received_bytes() {
+        // Nonzero padding after the member field, which marshaling would replace
+        let mut bytes = SIGNAL.to_vec();
+        bytes[87] = 1;
+        let lenient = ParseOptions::lenient();
+        let mut m =
+            Message::read_with_options(bytes.as_slice(), lenient, Limits::default()).unwrap();
+        let mut stored = Vec::new();
+        assert_eq!(bytes.len() as u64, m.to_writer(&mut stored).unwrap());
+        assert_eq!(bytes, stored);
+
+        // Modifying the header discards the received header
+        m.set_no_auto_start(true);
+        assert_eq!(None, m.header_bytes());
+        let mut stored = Vec::new();
+        m.to_writer(&mut stored).unwrap();
+        assert_eq!(0, stored[87]);
+        assert_eq!(&bytes[96..], &stored[96..]);
+    }
+
+    #[test]
+    fn unix_fds_limit() {
+        let mut fields = signal_fields();
+        fields.set_unix_fds(Some(MAX_MESSAGE_UNIX_FDS));
+        assert_eq!(Ok(()), signal_header(fields.clone()).validate_outgoing());
+        let m = Message {
+            header: signal_header(fields.clone()),
+            body: Body::default(),
+            raw_header: None,
+        };
+        let mut buffer = Vec::new();
+        m.write(&mut buffer).unwrap();
+        assert!(Message::read(buffer.as_slice()).is_ok());
+
+        fields.set_unix_fds(Some(MAX_MESSAGE_UNIX_FDS + 1));
+        let header = signal_header(fields);
+        assert_eq!(
+            Err(HeaderError::TooManyUnixFds(MAX_MESSAGE_UNIX_FDS + 1)),
+            header.validate_outgoing()
+        );
+        let m = Message {
+            header,
+            body: Body::default(),
+            raw_header: None,
+        };
+        let err = m.write(&mut Vec::new()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+
+        // Raise the announced count in the valid message
+        let position = buffer
+            .windows(4)
+            .rposition(|w| w == MAX_MESSAGE_UNIX_FDS.to_le_bytes())
+            .unwrap();
+        buffer[position..position + 4].copy_from_slice(&(MAX_MESSAGE_UNIX_FDS + 1).to_le_bytes());
+        let err = Message::read(buffer.as_slice()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn unknown_message_type() {
+        let mut bytes = SIGNAL.to_vec();
+        bytes[1] = 5;
+        let err = Message::read(bytes.as_slice()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        let lenient = ParseOptions::lenient();
+        let m = Message::read_with_options(bytes.as_slice(), lenient, Limits::default()).unwrap();
+        assert_eq!(MessageType::Unknown(5), m.header.message_type);
+        assert_eq!(vec![Value::from("hello")], m.body.args);
+        assert!(m
+            .pretty(&PrettyOptions::default())
+            .starts_with("unknown type 5 serial=1"));
+        let err = m.write(&mut Vec::new()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        let mut stored = Vec::new();
+        m.to_writer(&mut stored).unwrap();
+        assert_eq!(bytes, stored);
+
+        // The invalid type stays invalid
+        bytes[1] = 0;
+        assert!(Message::read_with_options(bytes.as_slice(), lenient, Limits::default()).is_err());
+    }
+
+    #[test]
+    fn header_field_code_try_from() {
+        assert!(HeaderFieldCode::try_from(0).is_err());
+        for code in 1..=9 {
+            assert_eq!(code, HeaderFieldCode::try_from(code).unwrap() as u8);
+        }
+        for code in 10..=u8::MAX {
+            assert_eq!(
+                HeaderFieldCode::Unknown,
+                HeaderFieldCode::try_from(code).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_header_fields() {
+        let mut header_fields = signal_fields();
+        let variant = Variant::from_parts(Signature("ay".into()), Value::ByteArray(Vec::new()));
+        header_fields.unknown.push((42, variant.clone()));
+        let m = Message {
+            header: signal_header(header_fields),
+            body: Body::default(),
+            raw_header: None,
+        };
+
+        let mut buffer = Vec::new();
+        m.write(&mut buffer).unwrap();
+        let parsed = Message::read(buffer.as_slice()).unwrap();
+        assert_eq!(
+            vec![(42, &variant)],
+            parsed.header.header_fields.unknown().collect::<Vec<_>>()
+        );
+        assert_eq!(Some(buffer.as_slice()), parsed.header_bytes());
+
+        let mut forwarded = Vec::new();
+        parsed.write(&mut forwarded).unwrap();
+        assert_eq!(buffer, forwarded);
+    }
+
+    #[test]
+    fn header_fields_canonical_order() {
+        let mut header_fields = HeaderFields::default();
+        header_fields.set_unix_fds(Some(0));
+        header_fields.set_path(Some(ObjectPath("/path".to_string())));
+        let header = signal_header(header_fields);
+
+        let mut buffer = Vec::new();
+        let mut writer = DbusWriter::new(&mut buffer, Endianness::Little);
+        header.write(&mut writer).unwrap();
+        assert_eq!(HeaderFieldCode::Path as u8, buffer[16]);
+    }
+
+    #[test]
+    fn header_fields_accessors() {
+        let mut header_fields = HeaderFields::default();
+        header_fields.set_unix_fds(Some(0));
+        header_fields.set_unix_fds(Some(1));
+        header_fields.set_reply_serial(Some(Serial::FIRST));
+
+        assert_eq!(Some(1), header_fields.unix_fds());
+        assert_eq!(Some(Serial::FIRST), header_fields.reply_serial());
+        assert_eq!(None, header_fields.path());
+        assert_eq!(
+            vec![
+                HeaderField::ReplySerial(Serial::FIRST),
+                HeaderField::UnixFds(1)
+            ],
+            header_fields.to_vec()
+        );
+
+        header_fields.set_unix_fds(None);
+        assert_eq!(None, header_fields.unix_fds());
+    }
+}
+
+/// A message consists of a header and a body. If you think of a message as a package,
+/// the header is the address, and the body contains the package contents.
+/// Both header and body use the D-Bus [type system](https://dbus.freedesktop.org/doc/dbus-specification.html#type-system) and format for serializing data.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Message {
+    /// The message delivery system uses the header information to figure out
+    /// where to send the message and how to interpret it.
+    header: Header,
+    /// The body of the message is made up of zero or more arguments,
+    /// which are typed values, such as an integer or a byte array.
+    body: Body,
+    /// The header in wire format including its padding, as received when parsing a message.
+    raw_header: Option<Vec<u8>>,
+}
+
+impl Message {
+    /// A call of the method `member` on the object `path`, like `dbus_message_new_method_call`.
+    /// Without `interface` the receiver picks any method with that name.
+    fn method_call(
+        destination: Option<BusName>,
+        path: ObjectPath,
+        interface: Option<InterfaceName>,
+        member: MemberName,
+        serial: Serial,
+    ) -> Message {
+        let mut header_fields = HeaderFields::default();
+        header_fields.set_destination(destination);
+        header_fields.set_path(Some(path));
+        header_fields.set_interface(interface);
+        header_fields.set_member(Some(member));
+        Message::new(
+            MessageType::MethodCall,
+            HeaderFlags::empty(),
+            header_fields,
+            serial,
+        )
+    }
+
+    /// The signal `member` of `interface` emitted from the object `path`, like `dbus_message_new_signal`.
+    /// Signals never expect a reply.
+    fn signal(
+        path: ObjectPath,
+        interface: InterfaceName,
+        member: MemberName,
+        serial: Serial,
+    ) -> Message {
+        let mut header_fields = HeaderFields::default();
+        header_fields.set_path(Some(path));
+        header_fields.set_interface(Some(interface));
+        header_fields.set_member(Some(member));
+        Message::new(
+            MessageType::Signal,
+            HeaderFlags::NO_REPLY_EXPECTED,
+            header_fields,
+            serial,
+        )
+    }
+
+    fn new(
+        message_type: MessageType,
+        flags: HeaderFlags,
+        header_fields: HeaderFields,
+        serial: Serial,
+    ) -> Message {
+        let header = Header {
+            endianess_flag: EndianessFlag::default(),
+            message_type,
+            flags,
+            major_protocol_version: MajorProtocolVersion(1),
+            length_message_body: 0,
+            serial,
+            header_fields,
+        };
+        Message {
+            header,
+            body: Body::default(),
+            raw_header: None,
+        }
+    }
+
+    /// A method return replying to `call`. The reply is addressed to the sender of the call
+    /// and uses the same byte order, `serial` is the serial of the reply itself.
+    fn method_return(call: &Message, serial: Serial) -> Message {
+        Message::new_reply(call, MessageType::MethodReturn, serial, Body::default())
+    }
+
+    /// An error replying to `call`, carrying `text` as human readable error message.
+    fn error(
+        call: &Message,
+        serial: Serial,
+        error_name: ErrorName,
+        text: &str,
+    ) -> Result<Message, io::Error> {
+        let body = Body {
+            args: vec![Value::from(text)],
+            raw: None,
+        };
+        let mut reply = Message::new_reply(call, MessageType::Error, serial, body);
+        reply.header.header_fields.set_error_name(Some(error_name));
+        reply
+            .header
+            .header_fields
+            .set_signature(Some(Signature("s".into())));
+        reply.header.length_message_body = reply.body.marshaled_len(
+            reply.header.endianess_flag,
+            reply.header.header_fields.signature(),
+        )?;
+        Ok(reply)
+    }
+
+    fn new_reply(call: &Message, message_type: MessageType, serial: Serial, body: Body) -> Message {
+        let call_fields = &call.header.header_fields;
+        let mut header_fields = HeaderFields::default();
+        header_fields.set_reply_serial(Some(call.header.serial));
+        header_fields.set_destination(call_fields.sender().cloned());
+        header_fields.set_sender(call_fields.destination().cloned());
+
+        let header = Header {
+            endianess_flag: call.header.endianess_flag,
+            message_type,
+            flags: HeaderFlags::NO_REPLY_EXPECTED,
+            major_protocol_version: call.header.major_protocol_version,
+            length_message_body: 0,
+            serial,
+            header_fields,
+        };
+
+        Message {
+            header,
+            body,
+            raw_header: None,
+        }
+    }
+
+    /// The object to send a call to, or the object a signal is emitted from.
+    fn path(&self) -> Option<&ObjectPath> {
+        self.header.header_fields.path()
+    }
+
+    /// The interface to invoke a method call on, or that a signal is emitted from.
+    fn interface(&self) -> Option<&InterfaceName> {
+        self.header.header_fields.interface()
+    }
+
+    /// The member, either the method name or signal name.
+    fn member(&self) -> Option<&MemberName> {
+        self.header.header_fields.member()
+    }
+
+    /// The name of the error that occurred, for errors.
+    fn error_name(&self) -> Option<&ErrorName> {
+        self.header.header_fields.error_name()
+    }
+
+    /// The serial of the message this message is a reply to.
+    fn reply_serial(&self) -> Option<Serial> {
+        self.header.header_fields.reply_serial()
+    }
+
+    /// The name of the connection this message is intended for.
+    fn destination(&self) -> Option<&BusName> {
+        self.header.header_fields.destination()
+    }
+
+    /// Unique name of the sending connection.
+    fn sender(&self) -> Option<&BusName> {
+        self.header.header_fields.sender()
+    }
+
+    /// The signature of the body, `None` if omitted which implies an empty body.
+    fn signature(&self) -> Option<&Signature> {
+        self.header.header_fields.signature()
+    }
+
+    /// The number of Unix file descriptors that accompany the message.
+    fn unix_fds(&self) -> Option<u32> {
+        self.header.header_fields.unix_fds()
+    }
+
+    /// This message does not expect method return replies or error replies.
+    fn no_reply_expected(&self) -> bool {
+        self.header.flags.contains(HeaderFlags::NO_REPLY_EXPECTED)
+    }
+
+    fn set_no_reply_expected(&mut self, value: bool) {
+        self.header.flags.set(HeaderFlags::NO_REPLY_EXPECTED, value);
+        self.raw_header = None;
+    }
+
+    /// The bus must not launch an owner for the destination name in response to this message.
+    fn no_auto_start(&self) -> bool {
+        self.header.flags.contains(HeaderFlags::NO_AUTO_START)
+    }
+
+    fn set_no_auto_start(&mut self, value: bool) {
+        self.header.flags.set(HeaderFlags::NO_AUTO_START, value);
+        self.raw_header = None;
+    }
+
+    /// The caller is prepared to wait for interactive authorization.
+    fn allow_interactive_authorization(&self) -> bool {
+        self.header
+            .flags
+            .contains(HeaderFlags::ALLOW_INTERACTIVE_AUTHORIZATION)
+    }
+
+    fn set_allow_interactive_authorization(&mut self, value: bool) {
+        self.header
+            .flags
+            .set(HeaderFlags::ALLOW_INTERACTIVE_AUTHORIZATION, value);
+        self.raw_header = None;
+    }
+
+    /// Attaches an already marshaled body, e.g. one received on another connection,
+    /// instead of marshaling arguments again. The body must be in the byte order of this
+    /// message and match `signature`, `unix_fds` is the number of file descriptors it refers to.
+    fn set_body_raw(
+        &mut self,
+        signature: Signature,
+        bytes: Vec<u8>,
+        unix_fds: u32,
+    ) -> Result<(), io::Error> {
+        if bytes.len() > MAX_MESSAGE_SIZE as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Body exceeds the maximum message size",
+            ));
+        }
+        let length_message_body = bytes.len() as u32;
+        let body = Body {
+            args: Vec::new(),
+            raw: Some(bytes),
+        };
+        body.check_against(self.header.endianess_flag, &signature)?;
+
+        let header_fields = &mut self.header.header_fields;
+        header_fields.set_signature(Some(signature).filter(|s| !s.0.is_empty()));
+        header_fields.set_unix_fds(Some(unix_fds).filter(|n| *n != 0));
+        self.header.length_message_body = length_message_body;
+        self.body = body;
+        self.raw_header = None;
+        Ok(())
+    }
+
+    /// Parses a complete message in wire format, the byte order is given by the endianess flag in byte 0.
+    pub(crate) fn read<T>(reader: T) -> Result<Message, io::Error>
+    where
+        T: io::Read,
+    {
+        Message::read_with_options(reader, ParseOptions::default(), Limits::default())
+    }
+
+    /// Parses a complete message, checking header and body as strictly as `options` demand
+    /// and rejecting messages exceeding `limits`.
+    pub(crate) fn read_with_options<T>(
+        reader: T,
+        options: ParseOptions,
+        limits: Limits,
+    ) -> Result<Message, io::Error>
+    where
+        T: io::Read,
+    {
+        // The byte order is only known once the flag in the first byte has been read
+        let mut reader = DbusReader::with_options(
+            Recorder {
+                reader,
+                bytes: Some(Vec::new()),
+            },
+            Endianness::Little,
+            options,
+        );
+        let endianess_flag = match reader.read_u8()? {
+            b'l' => EndianessFlag::LittleEndian,
+            b'B' => EndianessFlag::BigEndian,
+            x => {
+                let str_err = format!("Invalid endianess flag `{}`", x);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+            }
+        };
+        reader.set_endianness(endianess_flag.into());
+        Message::read_fields(&mut reader, endianess_flag, limits)
+    }
+
+    fn read_fields<T>(
+        reader: &mut DbusReader<Recorder<T>>,
+        endianess_flag: EndianessFlag,
+        limits: Limits,
+    ) -> Result<Message, io::Error>
+    where
+        T: io::Read,
+    {
+        let code = reader.read_u8()?;
+        let message_type = match MessageType::try_from(code) {
+            Ok(message_type) => message_type,
+            Err(_) if code != 0 && !reader.options().reject_unknown_message_types => {
+                MessageType::Unknown(code)
+            }
+            Err(err) => return Err(err),
+        };
+        let flags = HeaderFlags::from_bits_preserve(reader.read_u8()?);
+
+        let major_protocol_version = MajorProtocolVersion(reader.read_u8()?);
+        if major_protocol_version.0 != 1 {
+            let str_err = format!(
+                "Unsupported major protocol version `{}`",
+                major_protocol_version.0
+            );
+            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+        }
+
+        let length_message_body = reader.read_u32()?;
+        let serial = Serial::try_from(reader.read_u32()?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let header_fields = HeaderFields::read(reader, length_message_body, limits)?;
+        reader.read_padding(8)?;
+        let raw_header = reader.get_mut().bytes.take();
+        let raw = reader.read_bytes(u64::from(length_message_body))?;
+        let args = Body::read_args(
+            endianess_flag,
+            &raw,
+            header_fields.signature(),
+            reader.options(),
+        )?;
+
+        let header = Header {
+            endianess_flag,
+            message_type,
+            flags,
+            major_protocol_version,
+            length_message_body,
+            serial,
+            header_fields,
+        };
+        header
+            .validate()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let body = Body {
+            args,
+            raw: Some(raw),
+        };
+        Ok(Message {
+            header,
+            body,
+            raw_header,
+        })
+    }
+
+    /// The same message in the byte order `target`, e.g. for bridging between
+    /// big and little endian hosts. All header fields and arguments are preserved, a received
+    /// body is converted by reversing its multi-byte values in place, so it is kept exactly,
+    /// including any values only accepted by lenient parsing.
+    fn convert_endianness(&self, target: EndianessFlag) -> Result<Message, io::Error> {
+        let mut message = self.clone();
+        if target == self.header.endianess_flag {
+            return Ok(message);
+        }
+
+        let signature = self.header.header_fields.signature();
+        match self.body.raw.as_deref() {
+            Some(raw) => {
+                let swapped = Body::swap_endianness(raw, self.header.endianess_flag, signature)?;
+                message.body.raw = Some(swapped);
+            }
+            None => {
+                message.header.length_message_body = self.body.marshaled_len(target, signature)?
+            }
+        }
+        message.raw_header = None;
+        message.header.endianess_flag = target;
+        Ok(message)
+    }
+
+    /// Writes the message in wire format to `writer`, returning the number of bytes written.
+    /// Parsed messages are written exactly as received, others are marshaled. Unlike sending,
+    /// this does not restrict which messages may be written, so any received message can be stored.
+    pub(crate) fn to_writer<T: io::Write>(&self, mut writer: T) -> Result<u64, io::Error> {
+        if let (Some(header), Some(body)) = (self.header_bytes(), self.body_bytes()) {
+            writer.write_all(header)?;
+            writer.write_all(body)?;
+            return Ok((header.len() as u64).saturating_add(body.len() as u64));
+        }
+        self.header
+            .validate()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        self.marshal_with_limits(writer, Limits::default())
+    }
+
+    /// Reads exactly one message from `reader`, leaving any following data unread.
+    pub(crate) fn from_reader<T: io::Read>(reader: T) -> Result<Message, io::Error> {
+        Message::read(reader)
+    }
+
+    /// The header in wire format, including the padding up to the body, for parsed messages.
+    /// These are the bytes as received, `None` once the header has been modified.
+    fn header_bytes(&self) -> Option<&[u8]> {
+        self.raw_header.as_deref()
+    }
+
+    /// The body in wire format, for parsed messages or if a pre-marshaled body has been attached.
+    fn body_bytes(&self) -> Option<&[u8]> {
+        self.body.raw.as_deref()
+    }
+
+    /// The header fields on one line followed by the indented arguments, similar to the
+    /// output of `dbus-monitor`.
+    pub(crate) fn pretty(&self, options: &PrettyOptions) -> String {
+        let fields = &self.header.header_fields;
+        let mut pretty = match self.header.message_type {
+            MessageType::Invalid => "invalid".to_string(),
+            MessageType::MethodCall => "method call".to_string(),
+            MessageType::MethodReturn => "method return".to_string(),
+            MessageType::Error => "error".to_string(),
+            MessageType::Signal => "signal".to_string(),
+            MessageType::Unknown(code) => format!("unknown type {}", code),
+        };
+        if let Some(sender) = fields.sender() {
+            pretty.push_str(&format!(" sender={}", sender.as_ref()));
+        }
+        if let Some(destination) = fields.destination() {
+            pretty.push_str(&format!(" destination={}", destination.as_ref()));
+        }
+        pretty.push_str(&format!(" serial={}", self.header.serial.get()));
+        if let Some(reply_serial) = fields.reply_serial() {
+            pretty.push_str(&format!(" reply_serial={}", reply_serial.get()));
+        }
+        if let Some(path) = fields.path() {
+            pretty.push_str(&format!(" path={}", path.0));
+        }
+        if let Some(interface) = fields.interface() {
+            pretty.push_str(&format!(" interface={}", interface.as_ref()));
+        }
+        if let Some(member) = fields.member() {
+            pretty.push_str(&format!(" member={}", member.as_ref()));
+        }
+        if let Some(error_name) = fields.error_name() {
+            pretty.push_str(&format!(" error_name={}", error_name.as_ref()));
+        }
+        for arg in self.body.args.iter() {
+            let indent = " ".repeat(options.indent);
+            let arg = arg.pretty(options).to_string();
+            pretty.push_str(&format!(
+                "\n{}{}",
+                indent,
+                arg.replace('\n', &format!("\n{}", indent))
+            ));
+        }
+        pretty
+    }
+
+    /// The body is marshaled first, so the header always announces its actual length
+    /// regardless of `length_message_body`.
+    fn write<T>(&self, writer: T) -> Result<u64, io::Error>
+    where
+        T: io::Write,
+    {
+        self.write_with_limits(writer, Limits::default())
+    }
+
+    /// Marshals the message, failing without writing anything if it exceeds `limits`.
+    fn write_with_limits<T>(&self, writer: T, limits: Limits) -> Result<u64, io::Error>
+    where
+        T: io::Write,
+    {
+        self.header
+            .validate_outgoing()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        self.marshal_with_limits(writer, limits)
+    }
+
+    /// Marshals header and body without checking the header, see `write_with_limits`.
+    fn marshal_with_limits<T>(&self, mut writer: T, limits: Limits) -> Result<u64, io::Error>
+    where
+        T: io::Write,
+    {
+        let body = self.body.marshal(
+            self.header.endianess_flag,
+            self.header.header_fields.signature(),
+        )?;
+        if body.len() > limits.max_message_size() as usize {
+            let str_err = format!("Message body size `{}` exceeds maximum", body.len());
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, str_err));
+        }
+
+        let mut header = Vec::new();
+        let mut header_writer = DbusWriter::new(&mut header, self.header.endianess_flag.into());
+        self.header
+            .write_with_body_length(&mut header_writer, body.len() as u32)?;
+
+        let message_size = header.len().saturating_add(body.len());
+        if message_size > limits.max_message_size() as usize {
+            let str_err = format!("Message size `{}` exceeds maximum", message_size);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, str_err));
+        }
+        writer.write_all(&header)?;
+        writer.write_all(&body)?;
+        Ok(message_size as u64)
+    }
+}
+
+/// Assembles a `Message` from its header fields and arguments. The body signature is
+/// derived from the arguments, the header fields required by the message type are
+/// checked when the message is built.
+#[derive(Clone, Debug)]
+pub(crate) struct MessageBuilder {
+    message_type: MessageType,
+    flags: HeaderFlags,
+    header_fields: HeaderFields,
+    args: Vec<Value>,
+}
+
+impl MessageBuilder {
+    fn new(message_type: MessageType) -> MessageBuilder {
+        MessageBuilder {
+            message_type,
+            flags: HeaderFlags::empty(),
+            header_fields: HeaderFields::default(),
+            args: Vec::new(),
+        }
+    }
+
+    /// The connection the message is intended for.
+    fn destination(mut self, destination: BusName) -> MessageBuilder {
+        self.header_fields.set_destination(Some(destination));
+        self
+    }
+
+    /// The object to send a call to, or the object a signal is emitted from.
+    fn path(mut self, path: ObjectPath) -> MessageBuilder {
+        self.header_fields.set_path(Some(path));
+        self
+    }
+
+    /// The interface to invoke a method call on, or that a signal is emitted from.
+    fn interface(mut self, interface: InterfaceName) -> MessageBuilder {
+        self.header_fields.set_interface(Some(interface));
+        self
+    }
 
-        let v = create_libdbus_signal();
-        println!("DBUS Message Length(): {:X?}", v);
+    /// The method name or signal name.
+    fn member(mut self, member: MemberName) -> MessageBuilder {
+        self.header_fields.set_member(Some(member));
+        self
+    }
 
-        let header = Header {
-            endianess_flag: EndianessFlag::LittleEndian,
-            message_type: MessageType::Signal,
-            flags: HeaderFlags::NO_REPLY_EXPECTED,
-            major_protocol_version: MajorProtocolVersion(1),
-            length_message_body: 0,
-            serial: Serial(0),
-            header_fields: Vec::new(),
-        };
+    /// The name of the error that occurred.
+    fn error_name(mut self, error_name: ErrorName) -> MessageBuilder {
+        self.header_fields.set_error_name(Some(error_name));
+        self
+    }
 
-        let body = Body {};
+    /// The serial of the message this message is a reply to.
+    fn reply_serial(mut self, reply_serial: Serial) -> MessageBuilder {
+        self.header_fields.set_reply_serial(Some(reply_serial));
+        self
+    }
 
-        let m = Message { header, body };
+    /// Replaces the header flags.
+    fn with_flags(mut self, flags: HeaderFlags) -> MessageBuilder {
+        self.flags = flags;
+        self
+    }
 
-        let mut buffer = Vec::with_capacity(1024);
-        let len = m.write(&mut buffer).unwrap();
-        println!("DBUS Message Length({}): {:X?}", len, buffer);
+    /// Appends an argument to the body.
+    fn append_arg<T: Into<Value>>(mut self, arg: T) -> MessageBuilder {
+        self.args.push(arg.into());
+        self
+    }
 
-        assert_eq!(true, true);
+    /// The message with the serial `serial`, failing if a header field required
+    /// by the message type is missing or the body signature can not be determined.
+    fn build(self, serial: Serial) -> Result<Message, io::Error> {
+        let body = Body {
+            args: self.args,
+            raw: None,
+        };
+        let signature = body
+            .signature()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let mut header_fields = self.header_fields;
+        header_fields.set_signature(Some(signature).filter(|s| !s.0.is_empty()));
+
+        let mut message = Message::new(self.message_type, self.flags, header_fields, serial);
+        message.body = body;
+        message
+            .header
+            .validate_outgoing()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        Ok(message)
     }
 }
 
-/// The maximum length of a message, including header, header alignment padding,
-/// and body is 2 to the 27th power or 134217728 (128 MiB).
-/// Implementations must not send or accept messages exceeding this size.
-const MAX_MESSAGE_SIZE: u32 = 2 ^ 27;
-
-/// A message consists of a header and a body. If you think of a message as a package,
-/// the header is the address, and the body contains the package contents.
-/// Both header and body use the D-Bus [type system](https://dbus.freedesktop.org/doc/dbus-specification.html#type-system) and format for serializing data.
-struct Message {
-    /// The message delivery system uses the header information to figure out
-    /// where to send the message and how to interpret it.
-    header: Header,
-    /// The body of the message is made up of zero or more arguments,
-    /// which are typed values, such as an integer or a byte array.
-    body: Body,
+/// Keeps a copy of the bytes read until `bytes` is taken,
+/// used to retain the header of a parsed message in wire format.
+struct Recorder<T: io::Read> {
+    reader: T,
+    bytes: Option<Vec<u8>>,
 }
 
-impl Message {
-    fn write<T>(&self, writer: T) -> Result<u64, io::Error>
-    where
-        T: io::Write,
-    {
-        let mut bytes_written = 0;
-        let mut writer = DbusWriter::new(writer);
-        match self.header.endianess_flag {
-            EndianessFlag::LittleEndian => {
-                bytes_written += self
-                    .header
-                    .write::<T, LittleEndian>(&mut writer, bytes_written)?;
-                bytes_written += self
-                    .body
-                    .write::<T, LittleEndian>(&mut writer, bytes_written)?;
-            }
-            EndianessFlag::BigEndian => {
-                bytes_written += self
-                    .header
-                    .write::<T, BigEndian>(&mut writer, bytes_written)?;
-                bytes_written += self
-                    .body
-                    .write::<T, BigEndian>(&mut writer, bytes_written)?;
-            }
-        };
-        Ok(bytes_written)
+impl<T: io::Read> io::Read for Recorder<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let n = self.reader.read(buf)?;
+        if let (Some(bytes), Some(read)) = (self.bytes.as_mut(), buf.get(..n)) {
+            bytes.extend_from_slice(read);
+        }
+        Ok(n)
     }
 }
 
@@ -151,21 +1479,74 @@ enum EndianessFlag {
     BigEndian = b'B',
 }
 
+impl From<EndianessFlag> for Endianness {
+    fn from(endianess_flag: EndianessFlag) -> Endianness {
+        match endianess_flag {
+            EndianessFlag::LittleEndian => Endianness::Little,
+            EndianessFlag::BigEndian => Endianness::Big,
+        }
+    }
+}
+
+/// Outgoing messages default to the host byte order, so neither side has to swap bytes
+/// when both run on the same architecture. Messages may still override it individually.
+impl Default for EndianessFlag {
+    fn default() -> EndianessFlag {
+        if cfg!(target_endian = "big") {
+            EndianessFlag::BigEndian
+        } else {
+            EndianessFlag::LittleEndian
+        }
+    }
+}
+
 /// Message type. Unknown types must be ignored.
-#[repr(u8)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum MessageType {
     /// This is an invalid type.
-    Invalid = 0,
+    Invalid,
     /// Method call. This message type may prompt a reply.
-    MethodCall = 1,
+    MethodCall,
     /// Method reply with returned data.
-    MethodReturn = 2,
+    MethodReturn,
     /// Error reply. If the first argument exists
     /// and is a string, it is an error message.
-    Error = 3,
+    Error,
     /// Signal emission.
-    Signal = 4,
+    Signal,
+    /// A type defined by a later version of the specification, only accepted by
+    /// lenient parsing so the message can be skipped or inspected.
+    Unknown(u8),
+}
+
+impl MessageType {
+    fn code(self) -> u8 {
+        match self {
+            MessageType::Invalid => 0,
+            MessageType::MethodCall => 1,
+            MessageType::MethodReturn => 2,
+            MessageType::Error => 3,
+            MessageType::Signal => 4,
+            MessageType::Unknown(code) => code,
+        }
+    }
+}
+
+impl TryFrom<u8> for MessageType {
+    type Error = io::Error;
+
+    fn try_from(t: u8) -> Result<MessageType, io::Error> {
+        match t {
+            1 => Ok(MessageType::MethodCall),
+            2 => Ok(MessageType::MethodReturn),
+            3 => Ok(MessageType::Error),
+            4 => Ok(MessageType::Signal),
+            x => {
+                let str_err = format!("Invalid message type `{}`", x);
+                Err(io::Error::new(io::ErrorKind::InvalidData, str_err))
+            }
+        }
+    }
 }
 
 /// Major protocol version of the sending application.
@@ -175,15 +1556,7 @@ enum MessageType {
 pub struct MajorProtocolVersion(pub u8);
 
 impl DbusWrite for MajorProtocolVersion {
-    fn write<T1, T2>(
-        &self,
-        writer: &mut DbusWriter<T1>,
-        bytes_written: u64,
-    ) -> Result<u64, io::Error>
-    where
-        T1: io::Write,
-        T2: ByteOrder,
-    {
+    fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64, io::Error> {
         writer.write_u8(self.0)
     }
 }
@@ -203,15 +1576,22 @@ bitflags! {
     }
 }
 
+impl HeaderFlags {
+    /// Unknown flags must be ignored, they are kept nevertheless
+    /// so that forwarding a message does not alter it.
+    fn from_bits_preserve(bits: u8) -> HeaderFlags {
+        // Bits without a named flag are not invalid, bitflags merely has no constant for them.
+        unsafe { HeaderFlags::from_bits_unchecked(bits) }
+    }
+}
+
 /// The array at the end of the header contains header fields,
 /// where each field is a 1-byte field code followed by a field value.
 /// A header must contain the required header fields for its message type,
 /// and zero or more of any optional header fields.
 #[repr(u8)]
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 enum HeaderFieldCode {
-    /// Not a valid field name (error if it appears in a message)
-    Invalid = 0,
     /// The object to send a call to, or the object a signal is emitted from.
     /// Required in `MessageType::MethodCall` and `MessageType::Signal`.
     Path = 1,
@@ -241,6 +1621,31 @@ enum HeaderFieldCode {
     /// The number of Unix file descriptors that accompany the message.
     /// If omitted, it is assumed that no Unix file descriptors accompany the message.
     UnixFds = 9,
+    /// Any code from 10 on, not defined by the specification. Such fields must be ignored.
+    Unknown,
+}
+
+impl TryFrom<u8> for HeaderFieldCode {
+    type Error = io::Error;
+
+    fn try_from(code: u8) -> Result<HeaderFieldCode, io::Error> {
+        match code {
+            0 => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid header field code `0`",
+            )),
+            1 => Ok(HeaderFieldCode::Path),
+            2 => Ok(HeaderFieldCode::Interface),
+            3 => Ok(HeaderFieldCode::Member),
+            4 => Ok(HeaderFieldCode::ErrorName),
+            5 => Ok(HeaderFieldCode::ReplySerial),
+            6 => Ok(HeaderFieldCode::Destination),
+            7 => Ok(HeaderFieldCode::Sender),
+            8 => Ok(HeaderFieldCode::Signature),
+            9 => Ok(HeaderFieldCode::UnixFds),
+            _ => Ok(HeaderFieldCode::Unknown),
+        }
+    }
 }
 
 /// The array at the end of the header contains header fields,
@@ -249,6 +1654,7 @@ enum HeaderFieldCode {
 /// and zero or more of any optional header fields.
 ///
 #[repr(u8)]
+#[derive(Clone, Debug, PartialEq)]
 enum HeaderField {
     /// Not a valid field name (error if it appears in a message)
     Invalid,
@@ -270,47 +1676,329 @@ enum HeaderField {
     ReplySerial(Serial),
     /// The name of the connection this message is intended for.
     /// Optional.
-    Destination(String),
+    Destination(BusName),
     /// Unique name of the sending connection. This field is usually only meaningful
     /// in combination with the message bus, but other servers may define their own meanings for it.
     /// Optional.
-    Sender(String),
+    Sender(BusName),
     /// The signature of the message body. If omitted, it is assumed to be the empty signature "".
     /// Optional.
     Signature(Signature),
     /// The number of Unix file descriptors that accompany the message.
     /// If omitted, it is assumed that no Unix file descriptors accompany the message.
     UnixFds(u32),
+    /// A field with a code not defined by the specification, along with its value.
+    /// It is ignored but preserved, so forwarded messages keep it.
+    Unknown(u8, Variant),
+}
+
+impl HeaderField {
+    /// The field code as marshaled, which for unknown fields is the code as received.
+    fn code_byte(&self) -> u8 {
+        let code = match self {
+            HeaderField::Invalid => return 0,
+            HeaderField::Unknown(code, _) => return *code,
+            HeaderField::Path(_) => HeaderFieldCode::Path,
+            HeaderField::Interface(_) => HeaderFieldCode::Interface,
+            HeaderField::Member(_) => HeaderFieldCode::Member,
+            HeaderField::ErrorName(_) => HeaderFieldCode::ErrorName,
+            HeaderField::ReplySerial(_) => HeaderFieldCode::ReplySerial,
+            HeaderField::Destination(_) => HeaderFieldCode::Destination,
+            HeaderField::Sender(_) => HeaderFieldCode::Sender,
+            HeaderField::Signature(_) => HeaderFieldCode::Signature,
+            HeaderField::UnixFds(_) => HeaderFieldCode::UnixFds,
+        };
+        code as u8
+    }
+
+    /// The signature of the variant holding the field value.
+    fn signature(&self) -> Result<Signature, io::Error> {
+        let signature = match self {
+            HeaderField::Invalid => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "HeaderField::Invalid can not be marshaled!",
+                ))
+            }
+            HeaderField::Path(_) => "o",
+            HeaderField::Interface(_)
+            | HeaderField::Member(_)
+            | HeaderField::ErrorName(_)
+            | HeaderField::Destination(_)
+            | HeaderField::Sender(_) => "s",
+            HeaderField::ReplySerial(_) | HeaderField::UnixFds(_) => "u",
+            HeaderField::Signature(_) => "g",
+            HeaderField::Unknown(_, variant) => return Ok(variant.signature().clone()),
+        };
+        Ok(Signature(signature.to_string().into()))
+    }
+}
+
+/// The header fields of a message, each field can be present at most once.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct HeaderFields {
+    path: Option<ObjectPath>,
+    interface: Option<InterfaceName>,
+    member: Option<MemberName>,
+    error_name: Option<ErrorName>,
+    reply_serial: Option<Serial>,
+    destination: Option<BusName>,
+    sender: Option<BusName>,
+    signature: Option<Signature>,
+    unix_fds: Option<u32>,
+    /// Fields with a code unknown to this implementation in order of appearance, these must be ignored.
+    unknown: Vec<(u8, Variant)>,
+}
+
+impl HeaderFields {
+    fn path(&self) -> Option<&ObjectPath> {
+        self.path.as_ref()
+    }
+
+    fn set_path(&mut self, path: Option<ObjectPath>) {
+        self.path = path;
+    }
+
+    fn interface(&self) -> Option<&InterfaceName> {
+        self.interface.as_ref()
+    }
+
+    fn set_interface(&mut self, interface: Option<InterfaceName>) {
+        self.interface = interface;
+    }
+
+    fn member(&self) -> Option<&MemberName> {
+        self.member.as_ref()
+    }
+
+    fn set_member(&mut self, member: Option<MemberName>) {
+        self.member = member;
+    }
+
+    fn error_name(&self) -> Option<&ErrorName> {
+        self.error_name.as_ref()
+    }
+
+    fn set_error_name(&mut self, error_name: Option<ErrorName>) {
+        self.error_name = error_name;
+    }
+
+    fn reply_serial(&self) -> Option<Serial> {
+        self.reply_serial
+    }
+
+    fn set_reply_serial(&mut self, reply_serial: Option<Serial>) {
+        self.reply_serial = reply_serial;
+    }
+
+    fn destination(&self) -> Option<&BusName> {
+        self.destination.as_ref()
+    }
+
+    fn set_destination(&mut self, destination: Option<BusName>) {
+        self.destination = destination;
+    }
+
+    fn sender(&self) -> Option<&BusName> {
+        self.sender.as_ref()
+    }
+
+    fn set_sender(&mut self, sender: Option<BusName>) {
+        self.sender = sender;
+    }
+
+    fn signature(&self) -> Option<&Signature> {
+        self.signature.as_ref()
+    }
+
+    fn set_signature(&mut self, signature: Option<Signature>) {
+        self.signature = signature;
+    }
+
+    fn unix_fds(&self) -> Option<u32> {
+        self.unix_fds
+    }
+
+    fn set_unix_fds(&mut self, unix_fds: Option<u32>) {
+        self.unix_fds = unix_fds;
+    }
+
+    /// Parses the header field array, each field is a STRUCT of field code and VARIANT value.
+    /// The size of the message follows from the array length and `length_message_body`, it is
+    /// checked against `limits` before any field is parsed.
+    fn read(
+        reader: &mut DbusReader<dyn io::Read + '_>,
+        length_message_body: u32,
+        limits: Limits,
+    ) -> Result<HeaderFields, io::Error> {
+        let len = reader.read_u32()?;
+        if len > MAX_ARRAY_LENGTH {
+            let str_err = format!("Header field array length `{}` exceeds maximum", len);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+        }
+        // The header ends after its alignment padding to an 8-boundary.
+        let header_length = reader
+            .position()
+            .saturating_add(u64::from(len))
+            .saturating_add(7)
+            & !7;
+        let message_size = header_length.saturating_add(u64::from(length_message_body));
+        if message_size > u64::from(limits.max_message_size()) {
+            let str_err = format!("Message size `{}` exceeds maximum", message_size);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+        }
+        reader.read_padding(8)?;
+        let end = reader.position().saturating_add(u64::from(len));
+
+        let mut fields = HeaderFields::default();
+        let mut seen = Vec::new();
+        while reader.position() < end {
+            reader.read_padding(8)?;
+            let code = reader.read_u8()?;
+            if seen.contains(&code) {
+                let str_err = format!("Header field `{}` appears twice", code);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+            }
+            seen.push(code);
+
+            let signature = reader.read_signature()?;
+            match HeaderFieldCode::try_from(code)? {
+                HeaderFieldCode::Path => {
+                    expect_signature(&signature, "o")?;
+                    fields.set_path(Some(reader.read_object_path()?));
+                }
+                HeaderFieldCode::Interface => {
+                    expect_signature(&signature, "s")?;
+                    let name = reader.read_string()?;
+                    fields.set_interface(Some(parse_name::<InterfaceName>(&name)?));
+                }
+                HeaderFieldCode::Member => {
+                    expect_signature(&signature, "s")?;
+                    let name = reader.read_string()?;
+                    fields.set_member(Some(parse_name::<MemberName>(&name)?));
+                }
+                HeaderFieldCode::ErrorName => {
+                    expect_signature(&signature, "s")?;
+                    let name = reader.read_string()?;
+                    fields.set_error_name(Some(parse_name::<ErrorName>(&name)?));
+                }
+                HeaderFieldCode::ReplySerial => {
+                    expect_signature(&signature, "u")?;
+                    let serial = Serial::try_from(reader.read_u32()?)
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                    fields.set_reply_serial(Some(serial));
+                }
+                HeaderFieldCode::Destination => {
+                    expect_signature(&signature, "s")?;
+                    let name = reader.read_string()?;
+                    fields.set_destination(Some(parse_name::<BusName>(&name)?));
+                }
+                HeaderFieldCode::Sender => {
+                    expect_signature(&signature, "s")?;
+                    let name = reader.read_string()?;
+                    fields.set_sender(Some(parse_name::<BusName>(&name)?));
+                }
+                HeaderFieldCode::Signature => {
+                    expect_signature(&signature, "g")?;
+                    fields.set_signature(Some(reader.read_signature()?));
+                }
+                HeaderFieldCode::UnixFds => {
+                    expect_signature(&signature, "u")?;
+                    let unix_fds = reader.read_u32()?;
+                    if unix_fds > MAX_MESSAGE_UNIX_FDS {
+                        let str_err = format!("Unix fd count `{}` exceeds maximum", unix_fds);
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+                    }
+                    fields.set_unix_fds(Some(unix_fds));
+                }
+                HeaderFieldCode::Unknown => {
+                    let value = Value::read(reader, &signature)?;
+                    fields
+                        .unknown
+                        .push((code, Variant::from_parts(signature, value)));
+                }
+            }
+        }
+
+        if reader.position() != end {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Header fields exceed the header field array length",
+            ));
+        }
+
+        Ok(fields)
+    }
+
+    /// Fields with codes unknown to this implementation as pair of code and value.
+    fn unknown(&self) -> impl Iterator<Item = (u8, &Variant)> {
+        self.unknown.iter().map(|(code, value)| (*code, value))
+    }
+
+    /// The fields that are present, known fields ordered by ascending field code so the
+    /// same set of fields always results in the same marshaled header, followed by unknown fields.
+    fn to_vec(&self) -> Vec<HeaderField> {
+        let mut fields = Vec::new();
+        fields.extend(self.path.clone().map(HeaderField::Path));
+        fields.extend(self.interface.clone().map(HeaderField::Interface));
+        fields.extend(self.member.clone().map(HeaderField::Member));
+        fields.extend(self.error_name.clone().map(HeaderField::ErrorName));
+        fields.extend(self.reply_serial.map(HeaderField::ReplySerial));
+        fields.extend(self.destination.clone().map(HeaderField::Destination));
+        fields.extend(self.sender.clone().map(HeaderField::Sender));
+        fields.extend(self.signature.clone().map(HeaderField::Signature));
+        fields.extend(self.unix_fds.map(HeaderField::UnixFds));
+        fields.extend(
+            self.unknown
+                .iter()
+                .map(|(code, variant)| HeaderField::Unknown(*code, variant.clone())),
+        );
+        fields
+    }
+}
+
+/// Header field values are variants, their signature must match the type of the field.
+fn expect_signature(signature: &Signature, expected: &str) -> Result<(), io::Error> {
+    if signature.0 != expected {
+        let str_err = format!(
+            "Invalid header field signature `{}`, expected `{}`",
+            signature.0, expected
+        );
+        return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+    }
+    Ok(())
+}
+
+fn parse_name<T>(name: &str) -> Result<T, io::Error>
+where
+    T: FromStr,
+    T::Err: std::fmt::Debug,
+{
+    T::from_str(name).map_err(|err| {
+        let str_err = format!("Invalid name `{}`: {:?}", name, err);
+        io::Error::new(io::ErrorKind::InvalidData, str_err)
+    })
 }
 
 impl DbusWrite for HeaderField {
-    fn write<T1, T2>(
-        &self,
-        writer: &mut DbusWriter<T1>,
-        bytes_written: u64,
-    ) -> Result<u64, io::Error>
-    where
-        T1: io::Write,
-        T2: ByteOrder,
-    {
+    fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64, io::Error> {
         match self {
             HeaderField::Invalid => Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
                 "HeaderField::Invalid can not be marshaled!",
             )),
-            HeaderField::Path(object_path) => object_path.write::<_, T2>(writer, bytes_written),
-            HeaderField::Interface(interface_name) => {
-                interface_name.write::<_, T2>(writer, bytes_written)
-            }
-            HeaderField::Member(member_name) => member_name.write::<_, T2>(writer, bytes_written),
-            HeaderField::ErrorName(error_name) => error_name.write::<_, T2>(writer, bytes_written),
-            HeaderField::ReplySerial(serial) => serial.write::<_, T2>(writer, bytes_written),
-            HeaderField::Destination(destination) => {
-                writer.write_string::<T2>(destination, bytes_written)
+            HeaderField::Path(object_path) => object_path.write(writer),
+            HeaderField::Interface(interface_name) => interface_name.write(writer),
+            HeaderField::Member(member_name) => member_name.write(writer),
+            HeaderField::ErrorName(error_name) => error_name.write(writer),
+            HeaderField::ReplySerial(serial) => serial.write(writer),
+            HeaderField::Destination(destination) => destination.write(writer),
+            HeaderField::Sender(sender) => sender.write(writer),
+            HeaderField::Signature(signature) => signature.write(writer),
+            HeaderField::UnixFds(fd) => writer.write_u32(*fd),
+            HeaderField::Unknown(_, variant) => {
+                variant.write_with_signature(writer, variant.signature())
             }
-            HeaderField::Sender(sender) => writer.write_string::<T2>(sender, bytes_written),
-            HeaderField::Signature(signature) => signature.write::<_, T2>(writer, bytes_written),
-            HeaderField::UnixFds(fd) => writer.write_u32::<T2>(*fd, bytes_written),
         }
     }
 }
@@ -320,6 +2008,7 @@ impl DbusWrite for HeaderField {
 /// If the header does not naturally end on an 8-byte boundary up to 7 bytes of
 /// nul-initialized alignment padding must be added.
 /// https://dbus.freedesktop.org/doc/dbus-specification.html#message-protocol-header-fields
+#[derive(Clone, Debug, PartialEq)]
 struct Header {
     endianess_flag: EndianessFlag,
     /// Message type. Unknown types must be ignored.
@@ -332,55 +2021,488 @@ struct Header {
     major_protocol_version: MajorProtocolVersion,
     /// Length in bytes of the message body, starting from the end of the header.
     /// The header ends after its alignment padding to an 8-boundary.
+    /// `Message::write` computes it from the body, so it does not need to be kept up to date.
     length_message_body: u32,
     /// The serial of this message, used as a cookie by the sender to identify
     /// the reply corresponding to this request. This must not be zero.
     serial: Serial,
     /// An array of zero or more header fields where the byte is the field code,
     /// and the variant is the field value. The message type determines which fields are required.
-    header_fields: Vec<(HeaderFieldCode, HeaderField)>,
+    header_fields: HeaderFields,
+}
+
+impl Header {
+    /// Checks that the header fields required by the message type are present.
+    fn validate(&self) -> Result<(), HeaderError> {
+        let fields = &self.header_fields;
+        let required: &[(HeaderFieldCode, bool)] = match self.message_type {
+            MessageType::Invalid => return Err(HeaderError::InvalidMessageType),
+            // The requirements of unknown types are unknown as well
+            MessageType::Unknown(_) => &[],
+            MessageType::MethodCall => &[
+                (HeaderFieldCode::Path, fields.path().is_some()),
+                (HeaderFieldCode::Member, fields.member().is_some()),
+            ],
+            MessageType::MethodReturn => &[(
+                HeaderFieldCode::ReplySerial,
+                fields.reply_serial().is_some(),
+            )],
+            MessageType::Error => &[
+                (HeaderFieldCode::ErrorName, fields.error_name().is_some()),
+                (
+                    HeaderFieldCode::ReplySerial,
+                    fields.reply_serial().is_some(),
+                ),
+            ],
+            MessageType::Signal => &[
+                (HeaderFieldCode::Path, fields.path().is_some()),
+                (HeaderFieldCode::Interface, fields.interface().is_some()),
+                (HeaderFieldCode::Member, fields.member().is_some()),
+            ],
+        };
+
+        let missing: Vec<HeaderFieldCode> = required
+            .iter()
+            .filter(|(_, present)| !present)
+            .map(|(code, _)| *code)
+            .collect();
+        if !missing.is_empty() {
+            return Err(HeaderError::MissingFields(self.message_type, missing));
+        }
+        Ok(())
+    }
+
+    /// Checks the header of a message about to be sent, which in addition to `validate`
+    /// must not use the interface reserved for locally generated messages.
+    fn validate_outgoing(&self) -> Result<(), HeaderError> {
+        self.validate()?;
+        if let MessageType::Unknown(_) = self.message_type {
+            return Err(HeaderError::InvalidMessageType);
+        }
+        if matches!(self.header_fields.interface(), Some(i) if i.is_reserved()) {
+            return Err(HeaderError::ReservedInterface);
+        }
+        match self.header_fields.unix_fds() {
+            Some(unix_fds) if unix_fds > MAX_MESSAGE_UNIX_FDS => {
+                Err(HeaderError::TooManyUnixFds(unix_fds))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The header does not meet the requirements of its message type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum HeaderError {
+    /// Messages of type `MessageType::Invalid` must neither be sent nor accepted,
+    /// messages of unknown types are only accepted.
+    InvalidMessageType,
+    /// Header fields required by the message type are absent.
+    MissingFields(MessageType, Vec<HeaderFieldCode>),
+    /// The interface `org.freedesktop.DBus.Local` is reserved and must not be sent.
+    ReservedInterface,
+    /// More Unix file descriptors are announced than a message may carry.
+    TooManyUnixFds(u32),
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeaderError::InvalidMessageType => write!(f, "Invalid message type"),
+            HeaderError::MissingFields(message_type, missing) => {
+                let missing: Vec<String> = missing.iter().map(|c| format!("{:?}", c)).collect();
+                write!(
+                    f,
+                    "Message of type `{:?}` lacks required header fields: {}",
+                    message_type,
+                    missing.join(", ")
+                )
+            }
+            HeaderError::ReservedInterface => write!(f, "Interface is reserved for local use"),
+            HeaderError::TooManyUnixFds(unix_fds) => {
+                write!(f, "Unix fd count `{}` exceeds maximum", unix_fds)
+            }
+        }
+    }
 }
 
+impl std::error::Error for HeaderError {}
+
 impl DbusWrite for Header {
-    fn write<T1, T2>(
+    fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64, io::Error> {
+        self.write_with_body_length(writer, self.length_message_body)
+    }
+}
+
+impl Header {
+    /// Writes the header announcing a body of `length_message_body` bytes.
+    fn write_with_body_length(
         &self,
-        writer: &mut DbusWriter<T1>,
-        bytes_written: u64,
-    ) -> Result<u64, io::Error>
-    where
-        T1: io::Write,
-        T2: ByteOrder,
-    {
-        let mut bytes_written = 0;
-        bytes_written += writer.write_u8(self.endianess_flag as u8)?;
-        bytes_written += writer.write_u8(self.message_type as u8)?;
-        bytes_written += writer.write_u8(self.flags.bits())?;
-        bytes_written += writer.write_u8(self.major_protocol_version.0)?;
+        writer: &mut DbusWriter<dyn io::Write + '_>,
+        length_message_body: u32,
+    ) -> Result<u64, io::Error> {
+        let mut bytes_written = writer.write_u8(self.endianess_flag as u8)?;
+        bytes_written = bytes_written.saturating_add(writer.write_u8(self.message_type.code())?);
+        bytes_written = bytes_written.saturating_add(writer.write_u8(self.flags.bits())?);
+        bytes_written =
+            bytes_written.saturating_add(writer.write_u8(self.major_protocol_version.0)?);
 
-        bytes_written += writer.write_u32::<T2>(self.length_message_body, bytes_written)?;
-        bytes_written += writer.write_u32::<T2>(self.serial.0, bytes_written)?;
+        bytes_written = bytes_written.saturating_add(writer.write_u32(length_message_body)?);
+        bytes_written = bytes_written.saturating_add(writer.write_u32(self.serial.get())?);
 
-        for (ref code, ref field) in self.header_fields.iter().by_ref() {
-            bytes_written += writer.write_u8(*code as u8)?;
-            bytes_written += field.write::<T1, T2>(writer, bytes_written)?;
-        }
-        writer.write_padding(bytes_written, 8)?;
+        // ARRAY of STRUCT of (BYTE, VARIANT)
+        let fields = self.header_fields.to_vec();
+        let fields_bytes_written = writer.write_array_with(8, |writer| {
+            let mut fields_bytes_written = 0u64;
+            for field in fields.iter() {
+                let field_bytes_written = writer.nested(|writer| {
+                    let padding = u64::from(writer.write_padding(8)?);
+                    let code = writer.write_u8(field.code_byte())?;
+                    let value = writer
+                        .write_variant_with(field.signature()?, |writer| field.write(writer))?;
+                    Ok(padding.saturating_add(code).saturating_add(value))
+                })?;
+                fields_bytes_written = fields_bytes_written.saturating_add(field_bytes_written);
+            }
+            Ok(fields_bytes_written)
+        })?;
+        bytes_written = bytes_written.saturating_add(fields_bytes_written);
+        bytes_written = bytes_written.saturating_add(u64::from(writer.write_padding(8)?));
         Ok(bytes_written)
     }
 }
 
-struct Body {}
+#[derive(Clone, Default, PartialEq)]
+struct Body {
+    /// The arguments of the message in order of their appearance in the signature.
+    args: Vec<Value>,
+    /// The body in wire format, as received when parsing a message.
+    /// If present it is written as is instead of marshaling `args`.
+    raw: Option<Vec<u8>>,
+}
+
+/// Arguments are abbreviated like in the `Debug` output of `Value`, raw bodies are only shown by length.
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Body")
+            .field("args", &self.args)
+            .field("raw", &self.raw.as_ref().map(Vec::len))
+            .finish()
+    }
+}
+
+impl Body {
+    /// The signature of the body, made up of the signatures of all arguments.
+    fn signature(&self) -> Result<Signature, ValueError> {
+        let mut signature = String::new();
+        for arg in self.args.iter() {
+            signature.push_str(&arg.signature()?.0);
+        }
+        Ok(Signature(signature.into()))
+    }
+
+    /// The arguments in human readable form, one per line.
+    fn pretty(&self, options: &PrettyOptions) -> String {
+        let args: Vec<String> = self
+            .args
+            .iter()
+            .map(|arg| arg.pretty(options).to_string())
+            .collect();
+        args.join("\n")
+    }
+
+    /// Unmarshals the arguments of a received body. If the signature header field is omitted
+    /// the signature is assumed to be empty, implying the body must be 0-length.
+    fn read_args(
+        endianess_flag: EndianessFlag,
+        raw: &[u8],
+        signature: Option<&Signature>,
+        options: ParseOptions,
+    ) -> Result<Vec<Value>, io::Error> {
+        let empty = Signature("".into());
+        let signature = signature.unwrap_or(&empty);
+
+        let mut reader = DbusReader::with_options(raw, endianess_flag.into(), options);
+        let args = Value::read_all(&mut reader, signature)?;
+        if reader.position() != raw.len() as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Body contains data beyond its signature",
+            ));
+        }
+        Ok(args)
+    }
 
-impl DbusWrite for Body {
-    fn write<T1, T2>(
+    /// The number of bytes of the marshaled body.
+    fn marshaled_len(
         &self,
-        writer: &mut DbusWriter<T1>,
-        bytes_written: u64,
-    ) -> Result<u64, io::Error>
-    where
-        T1: io::Write,
-        T2: ByteOrder,
-    {
-        Ok(0)
+        endianess_flag: EndianessFlag,
+        signature: Option<&Signature>,
+    ) -> Result<u32, io::Error> {
+        Ok(self.marshal(endianess_flag, signature)?.len() as u32)
+    }
+
+    /// The body in wire format, the body starts on an 8-byte boundary so alignment is relative to its start.
+    fn marshal(
+        &self,
+        endianess_flag: EndianessFlag,
+        signature: Option<&Signature>,
+    ) -> Result<Vec<u8>, io::Error> {
+        let mut buffer = Vec::new();
+        let mut writer = DbusWriter::new(&mut buffer, endianess_flag.into());
+        self.write_with_signature(&mut writer, signature)?;
+        Ok(buffer)
+    }
+
+    /// Marshals the arguments, each as the corresponding complete type of `signature`, the
+    /// signature header field. The types are not derived from the arguments, so empty arrays
+    /// marshal correctly. If the signature is omitted the body must be empty.
+    fn write_with_signature(
+        &self,
+        writer: &mut DbusWriter<dyn io::Write + '_>,
+        signature: Option<&Signature>,
+    ) -> Result<u64, io::Error> {
+        if let Some(raw) = self.raw.as_ref() {
+            return writer.write_bytes(raw);
+        }
+
+        let empty = Signature("".into());
+        let signature = signature.unwrap_or(&empty);
+        let mut complete_types = signature.complete_types();
+        let mut body_bytes_written = 0u64;
+        for arg in self.args.iter() {
+            let complete_type = complete_types.next().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Body has more arguments than its signature",
+                )
+            })?;
+            let arg_bytes_written =
+                arg.write_with_signature(writer, &Signature(complete_type.to_string().into()))?;
+            body_bytes_written = body_bytes_written.saturating_add(arg_bytes_written);
+        }
+        if complete_types.next().is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Body has fewer arguments than its signature",
+            ));
+        }
+        Ok(body_bytes_written)
+    }
+
+    /// Verifies that the marshaled body consists of exactly the values described by `signature`,
+    /// without unmarshaling them. Gateways can use this to validate forwarded messages.
+    fn check_against(
+        &self,
+        endianess_flag: EndianessFlag,
+        signature: &Signature,
+    ) -> Result<(), io::Error> {
+        signature.validate()?;
+        let marshaled;
+        let bytes = match self.raw.as_deref() {
+            Some(raw) => raw,
+            None => {
+                marshaled = self.marshal(endianess_flag, Some(signature))?;
+                marshaled.as_slice()
+            }
+        };
+        let mut reader = DbusReader::new(bytes, endianess_flag.into());
+        let mut types = signature.0.as_bytes();
+        while !types.is_empty() {
+            types = check_complete_type(&mut reader, types, ContainerDepth::default(), None)?;
+        }
+
+        if reader.position() != bytes.len() as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Body contains data beyond its signature",
+            ));
+        }
+        Ok(())
+    }
+
+    /// The marshaled body `raw` in the other byte order. Only the bytes of multi-byte values
+    /// are reversed, padding and the content of strings are kept as is. Values are not checked
+    /// any stricter than by lenient parsing, as the body has been accepted before.
+    fn swap_endianness(
+        raw: &[u8],
+        endianess_flag: EndianessFlag,
+        signature: Option<&Signature>,
+    ) -> Result<Vec<u8>, io::Error> {
+        let empty = Signature("".into());
+        let signature = signature.unwrap_or(&empty);
+
+        let mut swapped = raw.to_vec();
+        let mut reader =
+            DbusReader::with_options(raw, endianess_flag.into(), ParseOptions::lenient());
+        let mut types = signature.0.as_bytes();
+        while !types.is_empty() {
+            types = check_complete_type(
+                &mut reader,
+                types,
+                ContainerDepth::default(),
+                Some(&mut swapped),
+            )?;
+        }
+
+        if reader.position() != raw.len() as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Body contains data beyond its signature",
+            ));
+        }
+        Ok(swapped)
+    }
+}
+
+/// Aligns `reader` to the next value of `len` bytes. If `swapped` is given, the bytes of that
+/// value are reversed in it, `swapped` being a copy of the data `reader` reads.
+fn swap_next(
+    reader: &mut DbusReader<dyn io::Read + '_>,
+    swapped: &mut Option<&mut Vec<u8>>,
+    len: usize,
+) -> Result<(), io::Error> {
+    reader.read_padding(len as u64)?;
+    if let Some(swapped) = swapped {
+        let value = usize::try_from(reader.position())
+            .ok()
+            .and_then(|start| swapped.get_mut(start..start.checked_add(len)?))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "Value exceeds the body")
+            })?;
+        value.reverse();
+    }
+    Ok(())
+}
+
+/// Skips over the value of the first complete type in `types`, returning the remaining types.
+/// If `swapped` is given, the multi-byte values skipped are reversed in it, see `swap_next`.
+fn check_complete_type<'a>(
+    reader: &mut DbusReader<dyn io::Read + '_>,
+    types: &'a [u8],
+    depth: ContainerDepth,
+    mut swapped: Option<&mut Vec<u8>>,
+) -> Result<&'a [u8], io::Error> {
+    let (type_code, rest) = match types.split_first() {
+        Some((type_code, rest)) => (*type_code, rest),
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Signature ends within a complete type",
+            ))
+        }
+    };
+
+    match type_code {
+        b'y' => {
+            reader.read_u8()?;
+        }
+        b'b' => {
+            swap_next(reader, &mut swapped, 4)?;
+            reader.read_boolean()?;
+        }
+        b'n' => {
+            swap_next(reader, &mut swapped, 2)?;
+            reader.read_i16()?;
+        }
+        b'q' => {
+            swap_next(reader, &mut swapped, 2)?;
+            reader.read_u16()?;
+        }
+        b'i' => {
+            swap_next(reader, &mut swapped, 4)?;
+            reader.read_i32()?;
+        }
+        b'u' => {
+            swap_next(reader, &mut swapped, 4)?;
+            reader.read_u32()?;
+        }
+        b'x' => {
+            swap_next(reader, &mut swapped, 8)?;
+            reader.read_i64()?;
+        }
+        b't' => {
+            swap_next(reader, &mut swapped, 8)?;
+            reader.read_u64()?;
+        }
+        b'd' => {
+            swap_next(reader, &mut swapped, 8)?;
+            reader.read_f64()?;
+        }
+        b'h' => {
+            swap_next(reader, &mut swapped, 4)?;
+            reader.read_unix_fd()?;
+        }
+        b's' => {
+            swap_next(reader, &mut swapped, 4)?;
+            reader.read_string()?;
+        }
+        b'o' => {
+            swap_next(reader, &mut swapped, 4)?;
+            reader.read_object_path()?;
+        }
+        b'g' => {
+            reader.read_signature()?;
+        }
+        b'v' => {
+            let signature = reader.read_signature()?;
+            let rest_variant =
+                check_complete_type(reader, signature.0.as_bytes(), depth.variant()?, swapped)?;
+            if !rest_variant.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Variant signature must be a single complete type",
+                ));
+            }
+        }
+        b'a' => {
+            swap_next(reader, &mut swapped, 4)?;
+            let len = reader.read_u32()?;
+            if len > MAX_ARRAY_LENGTH {
+                let str_err = format!("Array length `{}` exceeds maximum", len);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+            }
+            let element_type_code = rest.first().map_or(' ', |c| char::from(*c));
+            reader.read_padding(Signature::alignment_of(element_type_code))?;
+
+            let end = reader.position().saturating_add(u64::from(len));
+            let mut rest_array = skip_complete_type(rest)?;
+            let element_depth = depth.array()?;
+            while reader.position() < end {
+                rest_array =
+                    check_complete_type(reader, rest, element_depth, swapped.as_deref_mut())?;
+            }
+            if reader.position() != end {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Array elements exceed the array length",
+                ));
+            }
+            return Ok(rest_array);
+        }
+        b'(' | b'{' => {
+            reader.read_padding(8)?;
+            let close = if type_code == b'(' { b')' } else { b'}' };
+            let field_depth = depth.structure()?;
+            let mut rest_struct = rest;
+            loop {
+                match rest_struct.split_first() {
+                    Some((c, rest)) if *c == close => return Ok(rest),
+                    _ => {
+                        rest_struct = check_complete_type(
+                            reader,
+                            rest_struct,
+                            field_depth,
+                            swapped.as_deref_mut(),
+                        )?
+                    }
+                }
+            }
+        }
+        x => {
+            let str_err = format!("Invalid type code `{}` in signature", char::from(x));
+            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+        }
     }
+    Ok(rest)
 }
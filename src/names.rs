@@ -1,5 +1,9 @@
+use crate::limits::MAX_NAME_LENGTH;
 use crate::writer::{DbusWrite, DbusWriter};
-use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fmt;
 use std::io;
 use std::str::FromStr;
 
@@ -41,11 +45,25 @@ mod tests {
             InterfaceName::from_str("Invalid.C|har")
         );
 
-        let valid_string = "Valid.Interface";
+        assert_eq!(
+            Err(InterfaceNameError::InvalidCharacter('-')),
+            InterfaceName::from_str("Invalid.Da-sh")
+        );
+
+        assert_eq!(
+            Err(InterfaceNameError::ElementsMustContainChars),
+            InterfaceName::from_str("Trailing.Period.")
+        );
+
+        let valid_string = "Valid.Interface_1";
         assert_eq!(
             Ok(InterfaceName(valid_string.to_string())),
             InterfaceName::from_str(valid_string)
         );
+        assert!(!InterfaceName::from_str(valid_string).unwrap().is_reserved());
+        assert!(InterfaceName::from_str("org.freedesktop.DBus.Local")
+            .unwrap()
+            .is_reserved());
     }
 
     #[test]
@@ -76,11 +94,36 @@ mod tests {
             BusName::from_str("Invalid.C|har")
         );
 
-        let valid_string = "Valid.BusName";
         assert_eq!(
-            Ok(BusName(valid_string.to_string())),
+            Err(BusNameError::ElementsMustContainChars),
+            BusName::from_str("Trailing.Period.")
+        );
+
+        assert_eq!(
+            Err(BusNameError::ElementMustNotBeginWithDigit),
+            BusName::from_str("Must.Not.Start.With.9Digit")
+        );
+
+        assert_eq!(
+            Err(BusNameError::MustContainPeriod),
+            BusName::from_str(":42")
+        );
+
+        assert_eq!(
+            Err(BusNameError::MustNotBeginWithPeriod),
+            BusName::from_str(":.1.42")
+        );
+
+        let valid_string = "Valid.Bus_Name";
+        assert_eq!(
+            Ok(BusName::WellKnown(valid_string.to_string())),
             BusName::from_str(valid_string)
         );
+
+        let unique = BusName::from_str(":1.42").unwrap();
+        assert_eq!(BusName::Unique(":1.42".to_string()), unique);
+        assert!(unique.is_unique());
+        assert_eq!(":1.42", unique.as_ref());
     }
 
     #[test]
@@ -106,11 +149,26 @@ mod tests {
             MemberName::from_str("1MustNotBeginWithDigit")
         );
 
-        let valid_string = "ValidMemberName";
         assert_eq!(
-            Ok(MemberName(valid_string.to_string())),
-            MemberName::from_str(valid_string)
+            Err(MemberNameError::InvalidCharacter('-')),
+            MemberName::try_from("Invalid-Dash")
         );
+
+        assert_eq!(
+            Err(MemberNameError::MustBeAtLeastOneByte),
+            MemberName::try_from("")
+        );
+
+        let valid_string = "Valid_MemberName1";
+        let member = MemberName::try_from(valid_string).unwrap();
+        assert_eq!(valid_string, member.as_ref());
+        assert_eq!(valid_string, member.to_string());
+        assert_eq!(Ok(member.clone()), MemberName::from_str(valid_string));
+
+        let borrowed = MemberName::from_static(valid_string).unwrap();
+        assert!(matches!(borrowed.0, Cow::Borrowed(_)));
+        assert_eq!(member, borrowed);
+        assert!(MemberName::from_static("Contains.Period").is_err());
     }
 
     #[test]
@@ -154,32 +212,27 @@ mod tests {
     }
 }
 
-/// There is a maximum name length of 255 which applies to bus names, interfaces, and members.
-pub const MAX_NAME_LENGHT: usize = 255;
-
 lazy_static! {
     /// The special message bus name org.freedesktop.DBus responds to a number of additional messages at the object path /org/freedesktop/DBus.
-    static ref ORG_FREEDESKTOP_DBUS: BusName = BusName("org.freedesktop.DBus".to_string());
+    static ref ORG_FREEDESKTOP_DBUS: BusName = BusName::WellKnown("org.freedesktop.DBus".to_string());
 }
 
-/// Connections have one or more bus names associated with them.
-/// A connection has exactly one bus name that is a unique connection name.
-/// The unique connection name remains with the connection for its entire lifetime.
-/// A bus name is of type STRING, meaning that it must be valid UTF-8.
+/// Interfaces have names with type STRING, meaning that they must be valid UTF-8.
+/// Interface names are composed of two or more elements separated by a period ('.'),
+/// e.g. "org.freedesktop.DBus.Properties". Elements contain only `[A-Z][a-z][0-9]_`
+/// and must not begin with a digit. Interface names must not exceed the maximum name length of 255.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct InterfaceName(String);
 
 impl DbusWrite for InterfaceName {
-    fn write<T1, T2>(
-        &self,
-        writer: &mut DbusWriter<T1>,
-        bytes_written: u64,
-    ) -> Result<u64, io::Error>
-    where
-        T1: io::Write,
-        T2: ByteOrder,
-    {
-        writer.write_string::<T2>(&self.0, bytes_written)
+    fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64, io::Error> {
+        writer.write_string(&self.0)
+    }
+}
+
+impl AsRef<str> for InterfaceName {
+    fn as_ref(&self) -> &str {
+        &self.0
     }
 }
 
@@ -192,14 +245,13 @@ pub enum InterfaceNameError {
     /// All elements must contain at least one character.
     ElementsMustContainChars,
 
-    /// Each element must only contain the ASCII characters "[A-Z][a-z][0-9]_-", with "-" discouraged in new bus names.
-    /// Only elements that are part of a unique connection name may begin with a digit, elements in other bus names must not begin with a digit.
+    /// Each element must only contain the ASCII characters "[A-Z][a-z][0-9]_".
     InvalidCharacter(char),
 
-    /// Bus names must contain at least one '.' (period) character (and thus at least two elements).
+    /// Interface names must contain at least one '.' (period) character (and thus at least two elements).
     MustContainPeriod,
 
-    /// Bus names must not begin with a '.' (period) character.
+    /// Interface names must not begin with a '.' (period) character.
     MustNotBeginWithPeriod,
 
     /// Elements must not begin with digit.
@@ -207,20 +259,24 @@ pub enum InterfaceNameError {
 }
 
 fn is_valid_interface_name_char(c: char) -> bool {
-    match c {
-        'A'..='Z' => true,
-        'a'..='z' => true,
-        '0'..='9' => true,
-        '-' => true,
-        '.' => true,
-        _ => false,
+    matches!(c, 'A'..='Z' | 'a'..='z' | '0'..='9' | '_')
+}
+
+/// The interface of messages generated locally by implementations, e.g. to report a disconnect.
+/// Messages on this interface must never be sent.
+const LOCAL_INTERFACE: &str = "org.freedesktop.DBus.Local";
+
+impl InterfaceName {
+    /// Whether this is the locally reserved interface, which must not be used on outgoing messages.
+    pub fn is_reserved(&self) -> bool {
+        self.0 == LOCAL_INTERFACE
     }
 }
 
 impl FromStr for InterfaceName {
     type Err = InterfaceNameError;
     fn from_str(s: &str) -> Result<InterfaceName, InterfaceNameError> {
-        if s.len() > MAX_NAME_LENGHT {
+        if s.len() > MAX_NAME_LENGTH {
             return Err(InterfaceNameError::ExceedsMaxSize);
         }
 
@@ -228,26 +284,22 @@ impl FromStr for InterfaceName {
             return Err(InterfaceNameError::MustNotBeginWithPeriod);
         }
 
-        let mut last_period_position = 0;
-        for (i, c) in s.char_indices() {
-            if !is_valid_interface_name_char(c) {
+        let mut element_count = 0;
+        for element in s.split('.') {
+            element_count += 1;
+            let first = element
+                .chars()
+                .next()
+                .ok_or(InterfaceNameError::ElementsMustContainChars)?;
+            if let Some(c) = element.chars().find(|c| !is_valid_interface_name_char(*c)) {
                 return Err(InterfaceNameError::InvalidCharacter(c));
             }
-
-            if c == '.' {
-                if last_period_position + 1 == i {
-                    return Err(InterfaceNameError::ElementsMustContainChars);
-                }
-                last_period_position = i;
-            } else {
-                // start of new element
-                if last_period_position + 1 == i && c.is_digit(10) {
-                    return Err(InterfaceNameError::ElementMustNotBeginWithDigit);
-                }
+            if first.is_ascii_digit() {
+                return Err(InterfaceNameError::ElementMustNotBeginWithDigit);
             }
         }
 
-        if last_period_position == 0 {
+        if element_count < 2 {
             return Err(InterfaceNameError::MustContainPeriod);
         }
 
@@ -260,19 +312,33 @@ impl FromStr for InterfaceName {
 /// The unique connection name remains with the connection for its entire lifetime.
 /// A bus name is of type STRING, meaning that it must be valid UTF-8.
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct BusName(String);
+pub enum BusName {
+    /// A unique connection name assigned by the message bus, starting with ':', e.g. ":1.42".
+    Unique(String),
+    /// A well-known name requested by a connection, e.g. "org.freedesktop.DBus".
+    WellKnown(String),
+}
+
+impl BusName {
+    pub fn is_unique(&self) -> bool {
+        match self {
+            BusName::Unique(_) => true,
+            BusName::WellKnown(_) => false,
+        }
+    }
+}
 
 impl DbusWrite for BusName {
-    fn write<T1, T2>(
-        &self,
-        writer: &mut DbusWriter<T1>,
-        bytes_written: u64,
-    ) -> Result<u64, io::Error>
-    where
-        T1: io::Write,
-        T2: ByteOrder,
-    {
-        writer.write_string::<T2>(&self.0, bytes_written)
+    fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64, io::Error> {
+        writer.write_string(self.as_ref())
+    }
+}
+
+impl AsRef<str> for BusName {
+    fn as_ref(&self) -> &str {
+        match self {
+            BusName::Unique(name) | BusName::WellKnown(name) => name,
+        }
     }
 }
 
@@ -286,7 +352,6 @@ pub enum BusNameError {
     ElementsMustContainChars,
 
     /// Each element must only contain the ASCII characters "[A-Z][a-z][0-9]_-", with "-" discouraged in new bus names.
-    /// Only elements that are part of a unique connection name may begin with a digit, elements in other bus names must not begin with a digit.
     InvalidCharacter(char),
 
     /// Bus names must contain at least one '.' (period) character (and thus at least two elements).
@@ -294,69 +359,84 @@ pub enum BusNameError {
 
     /// Bus names must not begin with a '.' (period) character.
     MustNotBeginWithPeriod,
-    // TODO
-    // Only elements that are part of a unique connection name may begin with a digit, elements in other bus names must not begin with a digit.
+
+    /// Only elements that are part of a unique connection name may begin with a digit,
+    /// elements in other bus names must not begin with a digit.
+    ElementMustNotBeginWithDigit,
 }
 
 fn is_valid_bus_name_char(c: char) -> bool {
-    match c {
-        'A'..='Z' => true,
-        'a'..='z' => true,
-        '0'..='9' => true,
-        '-' => true,
-        '.' => true,
-        _ => false,
-    }
+    matches!(c, 'A'..='Z' | 'a'..='z' | '0'..='9' | '_' | '-')
 }
 
 impl FromStr for BusName {
     type Err = BusNameError;
     fn from_str(s: &str) -> Result<BusName, BusNameError> {
-        if s.len() > MAX_NAME_LENGHT {
+        if s.len() > MAX_NAME_LENGTH {
             return Err(BusNameError::ExceedsMaxSize);
         }
 
-        if s.starts_with('.') {
+        let unique = s.starts_with(':');
+        let elements = if unique { &s[1..] } else { s };
+        if elements.starts_with('.') {
             return Err(BusNameError::MustNotBeginWithPeriod);
         }
 
-        let mut last_period_position = 0;
-        for (i, c) in s.char_indices() {
-            if !is_valid_bus_name_char(c) {
+        let mut element_count = 0;
+        for element in elements.split('.') {
+            element_count += 1;
+            let first = element
+                .chars()
+                .next()
+                .ok_or(BusNameError::ElementsMustContainChars)?;
+            if let Some(c) = element.chars().find(|c| !is_valid_bus_name_char(*c)) {
                 return Err(BusNameError::InvalidCharacter(c));
             }
-
-            if c == '.' {
-                if last_period_position + 1 == i {
-                    return Err(BusNameError::ElementsMustContainChars);
-                }
-                last_period_position = i;
+            if !unique && first.is_ascii_digit() {
+                return Err(BusNameError::ElementMustNotBeginWithDigit);
             }
         }
 
-        if last_period_position == 0 {
+        if element_count < 2 {
             return Err(BusNameError::MustContainPeriod);
         }
 
-        Ok(BusName(s.to_string()))
+        if unique {
+            Ok(BusName::Unique(s.to_string()))
+        } else {
+            Ok(BusName::WellKnown(s.to_string()))
+        }
     }
 }
 
 /// Member (i.e. method or signal) names.
+/// Names known at compile time can be borrowed with `MemberName::from_static` instead of allocating.
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct MemberName(String);
+pub struct MemberName(Cow<'static, str>);
+
+impl MemberName {
+    /// A member name borrowing `s`, without allocating.
+    pub fn from_static(s: &'static str) -> Result<MemberName, MemberNameError> {
+        validate_member_name(s)?;
+        Ok(MemberName(Cow::Borrowed(s)))
+    }
+}
 
 impl DbusWrite for MemberName {
-    fn write<T1, T2>(
-        &self,
-        writer: &mut DbusWriter<T1>,
-        bytes_written: u64,
-    ) -> Result<u64, io::Error>
-    where
-        T1: io::Write,
-        T2: ByteOrder,
-    {
-        writer.write_string::<T2>(&self.0, bytes_written)
+    fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64, io::Error> {
+        writer.write_string(&self.0)
+    }
+}
+
+impl AsRef<str> for MemberName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for MemberName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
     }
 }
 
@@ -375,42 +455,50 @@ pub enum MemberNameError {
     MustNotBeginWithDigit,
 
     /// Must be at least 1 byte in length.
-    // TODO verify this
     MustBeAtLeastOneByte,
 }
 
 fn is_valid_member_name_char(c: char) -> bool {
-    match c {
-        'A'..='Z' => true,
-        'a'..='z' => true,
-        '0'..='9' => true,
-        '-' => true,
-        _ => false,
-    }
+    matches!(c, 'A'..='Z' | 'a'..='z' | '0'..='9' | '_')
 }
 
-impl FromStr for MemberName {
-    type Err = MemberNameError;
-    fn from_str(s: &str) -> Result<MemberName, MemberNameError> {
-        if s.len() > MAX_NAME_LENGHT {
-            return Err(MemberNameError::ExceedsMaxSize);
-        }
+fn validate_member_name(s: &str) -> Result<(), MemberNameError> {
+    if s.is_empty() {
+        return Err(MemberNameError::MustBeAtLeastOneByte);
+    }
 
-        if s.starts_with(|c: char| c.is_digit(10)) {
-            return Err(MemberNameError::MustNotBeginWithDigit);
-        }
+    if s.len() > MAX_NAME_LENGTH {
+        return Err(MemberNameError::ExceedsMaxSize);
+    }
 
-        for c in s.chars() {
-            if c == '.' {
-                return Err(MemberNameError::MustNotContainPeriod);
-            }
+    if s.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(MemberNameError::MustNotBeginWithDigit);
+    }
 
-            if !is_valid_member_name_char(c) {
-                return Err(MemberNameError::InvalidCharacter(c));
-            }
+    for c in s.chars() {
+        if c == '.' {
+            return Err(MemberNameError::MustNotContainPeriod);
+        }
+
+        if !is_valid_member_name_char(c) {
+            return Err(MemberNameError::InvalidCharacter(c));
         }
+    }
+    Ok(())
+}
 
-        Ok(MemberName(s.to_string()))
+impl TryFrom<&str> for MemberName {
+    type Error = MemberNameError;
+    fn try_from(s: &str) -> Result<MemberName, MemberNameError> {
+        validate_member_name(s)?;
+        Ok(MemberName(Cow::Owned(s.to_string())))
+    }
+}
+
+impl FromStr for MemberName {
+    type Err = MemberNameError;
+    fn from_str(s: &str) -> Result<MemberName, MemberNameError> {
+        MemberName::try_from(s)
     }
 }
 
@@ -419,16 +507,14 @@ impl FromStr for MemberName {
 pub struct ErrorName(String);
 
 impl DbusWrite for ErrorName {
-    fn write<T1, T2>(
-        &self,
-        writer: &mut DbusWriter<T1>,
-        bytes_written: u64,
-    ) -> Result<u64, io::Error>
-    where
-        T1: io::Write,
-        T2: ByteOrder,
-    {
-        writer.write_string::<T2>(&self.0, bytes_written)
+    fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64, io::Error> {
+        writer.write_string(&self.0)
+    }
+}
+
+impl AsRef<str> for ErrorName {
+    fn as_ref(&self) -> &str {
+        &self.0
     }
 }
 
@@ -0,0 +1,240 @@
+//! Human readable formatting of values, similar to the output of `dbus-monitor`.
+//! Large payloads are abbreviated so they don't produce megabyte log lines.
+use std::borrow::Borrow;
+use std::fmt;
+
+use crate::type_system::Value;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn pretty_nested_values() {
+        let v = Value::from((7u8, vec!["a", "b"], Value::variant_of(-1i32).unwrap()));
+        assert_eq!(
+            "struct {\n   byte 7\n   array [\n      string \"a\"\n      string \"b\"\n   ]\n   variant int32 -1\n}",
+            v.pretty(&PrettyOptions::default()).to_string()
+        );
+    }
+
+    #[test]
+    fn pretty_byte_array() {
+        let v = Value::from(vec![1u8, 0xAB, 0xFF]);
+        assert_eq!(
+            "array of bytes [ 01 ab ff ]",
+            v.pretty(&PrettyOptions::default()).to_string()
+        );
+
+        let options = PrettyOptions {
+            hex_bytes: false,
+            max_array_elements: 1,
+            ..PrettyOptions::default()
+        };
+        assert_eq!(
+            "array [\n   byte 1\n   ... 2 more\n]",
+            v.pretty(&options).to_string()
+        );
+
+        let v = Value::ByteArray(vec![1, 0xAB, 0xFF]);
+        assert_eq!(
+            "array of bytes [ 01 ab ff ]",
+            v.pretty(&PrettyOptions::default()).to_string()
+        );
+        assert_eq!(
+            "array [\n   byte 1\n   ... 2 more\n]",
+            v.pretty(&options).to_string()
+        );
+    }
+
+    #[test]
+    fn debug_abbreviates() {
+        let v = Value::from(vec![0u8; 1 << 20]);
+        let debug = format!("{:?}", v);
+        assert!(debug.len() < 256);
+        assert_eq!(v.pretty(&PrettyOptions::default()).to_string(), debug);
+    }
+
+    #[test]
+    fn pretty_max_depth() {
+        let v = Value::from((vec![(1u16,)],));
+        let options = PrettyOptions {
+            max_depth: 2,
+            indent: 1,
+            ..PrettyOptions::default()
+        };
+        assert_eq!(
+            "struct {\n array [\n  struct {...}\n ]\n}",
+            v.pretty(&options).to_string()
+        );
+    }
+}
+
+/// Controls how much of a value is shown and how it is laid out.
+#[derive(Clone, Debug)]
+pub struct PrettyOptions {
+    /// Number of spaces each nesting level is indented by.
+    pub indent: usize,
+    /// Containers nested deeper than this are abbreviated as `...`.
+    pub max_depth: usize,
+    /// Array elements beyond this number are omitted.
+    pub max_array_elements: usize,
+    /// Show byte arrays as a single line of hex digits.
+    pub hex_bytes: bool,
+}
+
+impl Default for PrettyOptions {
+    fn default() -> PrettyOptions {
+        PrettyOptions {
+            indent: 3,
+            max_depth: 16,
+            max_array_elements: 64,
+            hex_bytes: true,
+        }
+    }
+}
+
+/// Displays a value according to the given options, see `Value::pretty`.
+pub struct Pretty<'a> {
+    value: &'a Value,
+    options: &'a PrettyOptions,
+}
+
+impl Value {
+    /// A `Display` implementation showing this value in a human readable way.
+    pub fn pretty<'a>(&'a self, options: &'a PrettyOptions) -> Pretty<'a> {
+        Pretty {
+            value: self,
+            options,
+        }
+    }
+}
+
+impl<'a> fmt::Display for Pretty<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_value(f, self.value, 0)
+    }
+}
+
+/// Values are debug formatted like `Value::pretty` with the default options, so logging
+/// a large value does not dump all of it.
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.pretty(&PrettyOptions::default()))
+    }
+}
+
+impl<'a> Pretty<'a> {
+    fn write_value(&self, f: &mut fmt::Formatter, value: &Value, depth: usize) -> fmt::Result {
+        match value {
+            Value::Byte(b) => write!(f, "byte {}", b),
+            Value::Boolean(b) => write!(f, "boolean {}", b),
+            Value::Int16(i) => write!(f, "int16 {}", i),
+            Value::Uint16(u) => write!(f, "uint16 {}", u),
+            Value::Int32(i) => write!(f, "int32 {}", i),
+            Value::Uint32(u) => write!(f, "uint32 {}", u),
+            Value::Int64(i) => write!(f, "int64 {}", i),
+            Value::Uint64(u) => write!(f, "uint64 {}", u),
+            Value::Double(d) => write!(f, "double {}", d),
+            Value::String(s) => write!(f, "string {:?}", s),
+            Value::ObjectPath(o) => write!(f, "object path {:?}", o.0),
+            Value::Signature(s) => write!(f, "signature {:?}", s.0),
+            Value::UnixFd(fd) => write!(f, "file descriptor {}", fd.0),
+            Value::Variant(v) => {
+                write!(f, "variant ")?;
+                self.write_value(f, v, depth)
+            }
+            Value::Array(elements) => {
+                if self.options.hex_bytes
+                    && !elements.is_empty()
+                    && elements.iter().all(|e| matches!(e, Value::Byte(_)))
+                {
+                    let bytes: Vec<u8> = elements
+                        .iter()
+                        .filter_map(|e| match e {
+                            Value::Byte(b) => Some(*b),
+                            _ => None,
+                        })
+                        .collect();
+                    return self.write_bytes(f, &bytes);
+                }
+                self.write_container(f, "array [", "]", elements.iter(), true, depth)
+            }
+            Value::ByteArray(bytes) => {
+                if self.options.hex_bytes && !bytes.is_empty() {
+                    return self.write_bytes(f, bytes);
+                }
+                let elements = bytes.iter().map(|b| Value::Byte(*b));
+                self.write_container(f, "array [", "]", elements, true, depth)
+            }
+            Value::Struct(fields) => {
+                self.write_container(f, "struct {", "}", fields.iter(), false, depth)
+            }
+            Value::DictEntry(k, v) => {
+                let entry = [k.as_ref(), v.as_ref()];
+                self.write_container(f, "dict entry(", ")", entry.iter().copied(), false, depth)
+            }
+        }
+    }
+
+    /// Writes the elements of a container on separate lines, indented one level deeper.
+    /// Only the first `max_array_elements` elements of arrays are shown.
+    fn write_container<I>(
+        &self,
+        f: &mut fmt::Formatter,
+        open: &str,
+        close: &str,
+        elements: I,
+        truncate: bool,
+        depth: usize,
+    ) -> fmt::Result
+    where
+        I: ExactSizeIterator,
+        I::Item: Borrow<Value>,
+    {
+        if depth >= self.options.max_depth {
+            return write!(f, "{}...{}", open, close);
+        }
+
+        let len = elements.len();
+        let shown = if truncate {
+            len.min(self.options.max_array_elements)
+        } else {
+            len
+        };
+
+        writeln!(f, "{}", open)?;
+        let indent = (depth + 1) * self.options.indent;
+        for element in elements.take(shown) {
+            write!(f, "{:indent$}", "", indent = indent)?;
+            self.write_value(f, element.borrow(), depth + 1)?;
+            writeln!(f)?;
+        }
+        if len > shown {
+            writeln!(f, "{:indent$}... {} more", "", len - shown, indent = indent)?;
+        }
+        write!(
+            f,
+            "{:indent$}{}",
+            "",
+            close,
+            indent = depth * self.options.indent
+        )
+    }
+
+    fn write_bytes(&self, f: &mut fmt::Formatter, bytes: &[u8]) -> fmt::Result {
+        write!(f, "array of bytes [")?;
+        for b in bytes.iter().take(self.options.max_array_elements) {
+            write!(f, " {:02x}", b)?;
+        }
+        if bytes.len() > self.options.max_array_elements {
+            write!(
+                f,
+                " ... {} more",
+                bytes.len() - self.options.max_array_elements
+            )?;
+        }
+        write!(f, " ]")
+    }
+}
@@ -1,24 +1,295 @@
-use crate::type_system::{ObjectPath, Signature};
-use crate::writer::DbusWrite;
-use byteorder::{ByteOrder, ReadBytesExt};
+//! The read path faces untrusted peers, every malformed input must result
+//! in an error instead of a panic or an unbounded allocation.
+#![deny(
+    clippy::arithmetic_side_effects,
+    clippy::expect_used,
+    clippy::indexing_slicing,
+    clippy::panic,
+    clippy::unwrap_used
+)]
+
+use crate::endianness::Endianness;
+use crate::limits::{MAX_ARRAY_LENGTH, MAX_MESSAGE_SIZE};
+use crate::type_system::{ObjectPath, Signature, StringError, UnixFd};
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
 use std::io;
+use std::io::Read;
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+
+    use super::*;
+    use crate::type_system::ObjectPathError;
+
+    quickcheck! {
+        fn read_basic_types_never_panics(bytes: Vec<u8>) -> bool {
+            let mut reader = DbusReader::new(bytes.as_slice(), Endianness::Little);
+            let _ = reader.read_u8();
+            let _ = reader.read_boolean();
+            let _ = reader.read_u16();
+            let _ = reader.read_u64();
+            true
+        }
+
+        fn read_string_never_panics(bytes: Vec<u8>) -> bool {
+            let mut reader = DbusReader::new(bytes.as_slice(), Endianness::Little);
+            let _ = reader.read_string();
+            let _ = reader.read_object_path();
+            let _ = reader.read_signature();
+            true
+        }
+    }
+
+    #[test]
+    fn read_basic_types() {
+        let bytes = [
+            7, 0, 0xFF, 0xFE, 0, 0, 0, 1, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ];
+        let mut reader = DbusReader::new(&bytes[..], Endianness::Big);
+        assert_eq!(7, u8::read(&mut reader).unwrap());
+        assert_eq!(-2, i16::read(&mut reader).unwrap());
+        assert!(bool::read(&mut reader).unwrap());
+        assert_eq!(u64::MAX, u64::read(&mut reader).unwrap());
+        assert_eq!(16, reader.position());
+    }
+
+    #[test]
+    fn read_f64() {
+        let bytes = 36.6f64.to_bits().to_le_bytes();
+        let mut reader = DbusReader::new(&bytes[..], Endianness::Little);
+        assert_eq!(36.6, f64::read(&mut reader).unwrap());
+    }
+
+    #[test]
+    fn read_padding_must_be_zero() {
+        let bytes = [1, 1, 2, 0];
+        let mut reader = DbusReader::new(&bytes[..], Endianness::Little);
+        reader.read_u8().unwrap();
+        let err = reader.read_u16().unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn read_lenient() {
+        let bytes = [1, 1, 2, 0, 2, 0, 0, 0, 2, 0, 0, 0, 0xC3, 0x28, b'\0'];
+        let mut reader = DbusReader::new(&bytes[..], Endianness::Little);
+        reader.read_u8().unwrap();
+        assert!(reader.read_u16().is_err());
+
+        let mut reader =
+            DbusReader::with_options(&bytes[..], Endianness::Little, ParseOptions::lenient());
+        reader.read_u8().unwrap();
+        assert_eq!(2, reader.read_u16().unwrap());
+        assert!(reader.read_boolean().unwrap());
+        assert_eq!("\u{FFFD}(", reader.read_string().unwrap());
+    }
+
+    #[test]
+    fn read_string_length_exceeds_input() {
+        let bytes = [0x10, 0x00, 0x00, 0x00, b'a', b'b', b'\0'];
+        let mut reader = DbusReader::new(&bytes[..], Endianness::Little);
+        let err = reader.read_string().unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    }
+
+    #[test]
+    fn read_string_malformed() {
+        let cases = [
+            (
+                &[2, 0, 0, 0, b'a', b'b', b'\n'][..],
+                StringError::MissingNulTerminator,
+            ),
+            (
+                &[2, 0, 0, 0, b'a', b'\0', b'\0'][..],
+                StringError::InteriorNul,
+            ),
+            (
+                &[2, 0, 0, 0, 0xC3, 0x28, b'\0'][..],
+                StringError::InvalidUtf8,
+            ),
+        ];
+        for (bytes, expected) in cases.iter() {
+            let mut reader = DbusReader::new(*bytes, Endianness::Little);
+            let err = reader.read_string().unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            assert_eq!(
+                Some(expected),
+                err.get_ref().and_then(|e| e.downcast_ref::<StringError>())
+            );
+        }
+    }
+
+    #[test]
+    fn read_object_path() {
+        let bytes = [4, 0, 0, 0, b'/', b'a', b'/', b'b', b'\0'];
+        let mut reader = DbusReader::new(&bytes[..], Endianness::Little);
+        assert_eq!(
+            ObjectPath("/a/b".to_string()),
+            reader.read_object_path().unwrap()
+        );
+
+        let bytes = [4, 0, 0, 0, b'/', b'a', b'-', b'b', b'\0'];
+        let mut reader = DbusReader::new(&bytes[..], Endianness::Little);
+        let err = reader.read_object_path().unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!(
+            Some(&ObjectPathError::InvalidCharacter('-')),
+            err.get_ref()
+                .and_then(|e| e.downcast_ref::<ObjectPathError>())
+        );
+    }
+
+    #[test]
+    fn read_string_exceeds_max_message_size() {
+        let bytes = [0xFF, 0xFF, 0xFF, 0xFF, b'a', b'b', b'\0'];
+        let mut reader = DbusReader::new(&bytes[..], Endianness::Little);
+        let err = reader.read_string().unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+}
 
 type Result<T> = std::result::Result<T, std::io::Error>;
 
-pub trait DbusRead<T> {
-    fn read<T1, T2>(&self, reader: &mut DbusReader<T1>) -> Result<T>
+/// Reads a value using the `ReadBytesExt` method `$read` in the reader's byte order.
+macro_rules! read_ordered {
+    ($self:ident, $read:ident) => {
+        match $self.endianness {
+            Endianness::Little => $self.reader.$read::<LittleEndian>(),
+            Endianness::Big => $self.reader.$read::<BigEndian>(),
+        }
+    };
+}
+
+/// Controls how strictly received data is checked against the specification.
+/// The default rejects everything the specification forbids, as services should.
+/// Bus monitors may prefer `ParseOptions::lenient` to inspect messages of misbehaving peers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Reject booleans other than 0 and 1 instead of reading any non-zero value as `true`.
+    pub reject_invalid_booleans: bool,
+    /// Reject strings that are not valid UTF-8 instead of replacing invalid sequences
+    /// with U+FFFD REPLACEMENT CHARACTER.
+    pub reject_invalid_utf8: bool,
+    /// Reject alignment padding that is not made up of nul bytes instead of skipping it.
+    pub reject_nonzero_padding: bool,
+    /// Reject messages of types unknown to this implementation instead of reading them
+    /// as `MessageType::Unknown`, which the specification says receivers must ignore.
+    pub reject_unknown_message_types: bool,
+}
+
+impl ParseOptions {
+    /// Recovers from every violation that does not affect the framing of the data.
+    pub fn lenient() -> ParseOptions {
+        ParseOptions {
+            reject_invalid_booleans: false,
+            reject_invalid_utf8: false,
+            reject_nonzero_padding: false,
+            reject_unknown_message_types: false,
+        }
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            reject_invalid_booleans: true,
+            reject_invalid_utf8: true,
+            reject_nonzero_padding: true,
+            reject_unknown_message_types: true,
+        }
+    }
+}
+
+/// Counterpart of `DbusWrite`, unmarshals a value of the implementing type.
+pub trait DbusRead {
+    fn read(reader: &mut DbusReader<dyn io::Read + '_>) -> Result<Self>
     where
-        T1: io::Read,
-        T2: ByteOrder;
+        Self: Sized;
 }
 
-pub struct DbusReader<T: io::Read> {
+/// Keeps track of the number of bytes consumed, which alignment padding is relative to.
+struct CountingReader<T: io::Read + ?Sized> {
+    position: u64,
     reader: T,
 }
 
+impl<T: io::Read + ?Sized> io::Read for CountingReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.position = self.position.saturating_add(n as u64);
+        Ok(n)
+    }
+}
+
+pub struct DbusReader<T: io::Read + ?Sized> {
+    endianness: Endianness,
+    options: ParseOptions,
+    reader: CountingReader<T>,
+}
+
 impl<T: io::Read> DbusReader<T> {
-    pub fn new(reader: T) -> DbusReader<T> {
-        DbusReader { reader }
+    pub fn new(reader: T, endianness: Endianness) -> DbusReader<T> {
+        DbusReader::with_options(reader, endianness, ParseOptions::default())
+    }
+
+    pub fn with_options(reader: T, endianness: Endianness, options: ParseOptions) -> DbusReader<T> {
+        DbusReader {
+            endianness,
+            options,
+            reader: CountingReader {
+                position: 0,
+                reader,
+            },
+        }
+    }
+}
+
+impl<T: io::Read + ?Sized> DbusReader<T> {
+    /// Byte order all multi-byte values are read in.
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    /// Changes the byte order of all following reads, e.g. once the endianness flag
+    /// at the start of a message header has been read.
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
+    }
+
+    /// How strictly the data read is checked.
+    pub fn options(&self) -> ParseOptions {
+        self.options
+    }
+
+    /// Mutable access to the underlying reader.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.reader.reader
+    }
+
+    /// Number of bytes read so far.
+    pub fn position(&self) -> u64 {
+        self.reader.position
+    }
+
+    /// Skip the padding up to the next multiple of `align_to`.
+    /// Alignment padding must always be made up of nul bytes, unless lenient parsing is enabled.
+    pub fn read_padding(&mut self, align_to: u64) -> Result<()> {
+        let misalignment = self.position().checked_rem(align_to).unwrap_or(0);
+        let padding_length = align_to
+            .saturating_sub(misalignment)
+            .checked_rem(align_to)
+            .unwrap_or(0);
+
+        for _ in 0..padding_length {
+            if self.reader.read_u8()? != 0 && self.options.reject_nonzero_padding {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Alignment padding must be nul bytes",
+                ));
+            }
+        }
+        Ok(())
     }
 
     pub fn read_invalid(&self) -> Result<()> {
@@ -34,11 +305,12 @@ impl<T: io::Read> DbusReader<T> {
     }
 
     /// As for UINT32, but only 0 and 1 are valid values.
-    pub fn read_boolean<T1: ByteOrder>(&mut self) -> Result<bool> {
-        let val = self.reader.read_u32::<T1>()?;
+    pub fn read_boolean(&mut self) -> Result<bool> {
+        let val = self.read_u32()?;
         match val {
             0 => Ok(false),
             1 => Ok(true),
+            _ if !self.options.reject_invalid_booleans => Ok(true),
             x => {
                 let str_err = format!("Invalid boolean `{}`", x);
                 Err(io::Error::new(io::ErrorKind::InvalidData, str_err))
@@ -47,77 +319,202 @@ impl<T: io::Read> DbusReader<T> {
     }
 
     /// 16-bit signed integer in the message's byte order.
-    pub fn read_i16<T1: ByteOrder>(&mut self) -> Result<i16> {
-        self.reader.read_i16::<T1>()
+    pub fn read_i16(&mut self) -> Result<i16> {
+        self.read_padding(2)?;
+        read_ordered!(self, read_i16)
     }
 
     /// 16-bit unsigned integer in the message's byte order.
-    pub fn read_u16<T1: ByteOrder>(&mut self) -> Result<u16> {
-        self.reader.read_u16::<T1>()
+    pub fn read_u16(&mut self) -> Result<u16> {
+        self.read_padding(2)?;
+        read_ordered!(self, read_u16)
     }
 
     /// 32-bit signed integer in the message's byte order.
-    pub fn read_i32<T1: ByteOrder>(&mut self) -> Result<i32> {
-        self.reader.read_i32::<T1>()
+    pub fn read_i32(&mut self) -> Result<i32> {
+        self.read_padding(4)?;
+        read_ordered!(self, read_i32)
     }
 
     /// 32-bit unsigned integer in the message's byte order.
-    pub fn read_u32<T1: ByteOrder>(&mut self) -> Result<u32> {
-        self.reader.read_u32::<T1>()
+    pub fn read_u32(&mut self) -> Result<u32> {
+        self.read_padding(4)?;
+        read_ordered!(self, read_u32)
     }
 
     /// 64-bit signed integer in the message's byte order.
-    pub fn read_i64<T1: ByteOrder>(&mut self) -> Result<i64> {
-        self.reader.read_i64::<T1>()
+    pub fn read_i64(&mut self) -> Result<i64> {
+        self.read_padding(8)?;
+        read_ordered!(self, read_i64)
     }
 
     /// 64-bit unsigned integer in the message's byte order.
-    pub fn read_u64<T1: ByteOrder>(&mut self) -> Result<u64> {
-        self.reader.read_u64::<T1>()
+    pub fn read_u64(&mut self) -> Result<u64> {
+        self.read_padding(8)?;
+        read_ordered!(self, read_u64)
+    }
+
+    /// 64-bit IEEE 754 double in the message's byte order.
+    pub fn read_f64(&mut self) -> Result<f64> {
+        self.read_padding(8)?;
+        read_ordered!(self, read_f64)
+    }
+
+    /// Unsigned 32-bit integer representing an index into an out-of-band array of file descriptors.
+    pub fn read_unix_fd(&mut self) -> Result<UnixFd> {
+        self.read_u32().map(UnixFd)
+    }
+
+    /// Exactly `len` bytes of raw data.
+    pub fn read_bytes(&mut self, len: u64) -> Result<Vec<u8>> {
+        // The buffer only grows with the data that was actually received,
+        // so a bogus length can not trigger a huge allocation.
+        let mut buffer = Vec::new();
+        let read = (&mut self.reader).take(len).read_to_end(&mut buffer)?;
+        if read as u64 != len {
+            let str_err = format!("Length `{}` exceeds remaining input", len);
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, str_err));
+        }
+        Ok(buffer)
     }
 
     /// A UINT32 indicating the string's length in bytes excluding its terminating nul,
     /// followed by non-nul string data of the given length, followed by a terminating nul byte.
-    pub fn read_string<T1: ByteOrder>(&mut self) -> Result<String> {
-        let len = self.reader.read_u32::<T1>()?;
-        let mut buffer = Vec::with_capacity(len as usize);
-        self.reader.read_exact(&mut buffer);
-
-        let str_temination = self.reader.read_u8()?;
-        if str_temination != b'\n' {
-            let str_err = format!("Invalid termination character `{}`", str_temination);
-            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+    pub fn read_string(&mut self) -> Result<String> {
+        let len = self.read_u32()?;
+        if len > MAX_MESSAGE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                StringError::ExceedsMaxSize,
+            ));
+        }
+        self.read_string_data(u64::from(len))
+    }
+
+    /// String data of `len` bytes followed by a terminating nul byte.
+    fn read_string_data(&mut self, len: u64) -> Result<String> {
+        let buffer = self.read_bytes(len)?;
+
+        if self.reader.read_u8()? != b'\0' {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                StringError::MissingNulTerminator,
+            ));
+        }
+        if buffer.contains(&b'\0') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                StringError::InteriorNul,
+            ));
         }
 
-        String::from_utf8(buffer).map_err(|err| {
-            let str_err = format!("UT8 error: `{}`", err);
-            io::Error::new(io::ErrorKind::InvalidData, str_err)
-        })
+        match String::from_utf8(buffer) {
+            Ok(s) => Ok(s),
+            Err(err) if !self.options.reject_invalid_utf8 => {
+                Ok(String::from_utf8_lossy(err.as_bytes()).into_owned())
+            }
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                StringError::InvalidUtf8,
+            )),
+        }
     }
 
     /// Exactly the same as STRING except the content must be a valid object path (see above).
-    pub fn read_object_path<T1: ByteOrder>(&mut self) -> Result<ObjectPath> {
-        let s = self.read_string::<T1>()?;
-        Ok(ObjectPath(s))
+    pub fn read_object_path(&mut self) -> Result<ObjectPath> {
+        let path = ObjectPath(self.read_string()?);
+        path.validate()?;
+        Ok(path)
     }
 
     /// The same as STRING except the length is a single byte (thus signatures
     /// have a maximum length of 255) and the content must be a valid signature (see above).
-    pub fn read_signature<T1: ByteOrder>(&mut self) -> Result<Signature> {
-        let s = self.read_string::<T1>()?;
-        Ok(Signature(s))
+    pub fn read_signature(&mut self) -> Result<Signature> {
+        let len = self.read_u8()?;
+        let signature = Signature(self.read_string_data(u64::from(len))?.into());
+        signature.validate()?;
+        Ok(signature)
+    }
+
+    /// A UINT32 giving the length of the array data in bytes, followed by alignment padding
+    /// to `element_alignment`, followed by the elements which are each read by `f`.
+    pub fn read_array_with<F>(&mut self, element_alignment: u64, mut f: F) -> Result<()>
+    where
+        F: FnMut(&mut Self) -> Result<()>,
+    {
+        let len = self.read_u32()?;
+        if len > MAX_ARRAY_LENGTH {
+            let str_err = format!("Array length `{}` exceeds maximum", len);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+        }
+        self.read_padding(element_alignment)?;
+
+        let end = self.position().saturating_add(u64::from(len));
+        while self.position() < end {
+            f(self)?;
+        }
+        if self.position() != end {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Array elements exceed the array length",
+            ));
+        }
+        Ok(())
     }
 
     // A UINT32 giving the length of the array data in bytes, followed by alignment
     // padding to the alignment boundary of the array element type, followed by each array element.
     // pub fn read_array<T1: ByteOrder, T2: DbusRead<T>>(&mut self, a: &[T2]) -> Result<Vec<T2>> {
-    //     let len = self.reader.read_u32::<T1>()?;
+    //     let len = read_ordered!(self, read_u32)?;
 
     //     let vec = Vec::with_capacity(len as usize);
     //     for x in 1..len {
-    //         let elem = self.reader.read::<T1>()?;
+    //         let elem = self.reader.read()?;
     //         vec.push(elem);
     //     }
     //     Ok(vec)
     // }
 }
+
+impl DbusReader<dyn io::Read + '_> {
+    /// A DICT_ENTRY is aligned to 8 bytes like a struct and holds exactly a key and a value.
+    pub fn read_dict_entry<K, V>(&mut self) -> Result<(K, V)>
+    where
+        K: DbusRead,
+        V: DbusRead,
+    {
+        self.read_padding(8)?;
+        let k = K::read(self)?;
+        let v = V::read(self)?;
+        Ok((k, v))
+    }
+}
+
+macro_rules! impl_dbus_read {
+    ($t:ty, $read:ident) => {
+        impl DbusRead for $t {
+            fn read(reader: &mut DbusReader<dyn io::Read + '_>) -> Result<$t> {
+                reader.$read()
+            }
+        }
+    };
+}
+
+impl DbusRead for u8 {
+    fn read(reader: &mut DbusReader<dyn io::Read + '_>) -> Result<u8> {
+        reader.read_u8()
+    }
+}
+
+impl_dbus_read!(bool, read_boolean);
+impl_dbus_read!(i16, read_i16);
+impl_dbus_read!(u16, read_u16);
+impl_dbus_read!(i32, read_i32);
+impl_dbus_read!(u32, read_u32);
+impl_dbus_read!(i64, read_i64);
+impl_dbus_read!(u64, read_u64);
+impl_dbus_read!(f64, read_f64);
+impl_dbus_read!(UnixFd, read_unix_fd);
+impl_dbus_read!(String, read_string);
+impl_dbus_read!(ObjectPath, read_object_path);
+impl_dbus_read!(Signature, read_signature);
@@ -1,15 +1,35 @@
-use byteorder::ByteOrder;
-use std::collections::HashMap;
+#![deny(
+    clippy::arithmetic_side_effects,
+    clippy::expect_used,
+    clippy::indexing_slicing,
+    clippy::panic,
+    clippy::unwrap_used
+)]
+// The module is private, so its impls for public types are not part of the crate's API either
+#![allow(clippy::missing_inline_in_public_items)]
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
+use std::fmt;
 use std::hash::{BuildHasher, Hash};
 use std::io;
+use std::num::NonZeroU32;
+use std::ops::{Deref, DerefMut};
 
+use crate::limits::{
+    MAX_ARRAY_LENGTH, MAX_ARRAY_NESTING_DEPTH, MAX_SIGNATURE_DEPTH, MAX_SIGNATURE_LENGTH,
+    MAX_STRUCT_NESTING_DEPTH,
+};
+use crate::reader::{DbusRead, DbusReader};
 use crate::writer::{DbusWrite, DbusWriter};
 
 #[cfg(test)]
+#[allow(clippy::indexing_slicing, clippy::unwrap_used)]
 mod tests {
 
     use super::*;
+    use crate::endianness::Endianness;
 
     #[test]
     fn type_code_basic_types() {
@@ -25,13 +45,39 @@ mod tests {
         assert_eq!("d", (36.6f64).to_type_code());
         assert_eq!("s", "abc".to_type_code());
         assert_eq!("o", ObjectPath("obj".to_string()).to_type_code());
-        assert_eq!("g", Signature("obj".to_string()).to_type_code());
+        assert_eq!("g", Signature("obj".into()).to_type_code());
     }
 
     #[test]
     fn type_code_vec() {
         let vec = vec!["Value1", "Value2"];
-        assert_eq!("ass", vec.to_type_code());
+        assert_eq!("as", vec.to_type_code());
+        assert_eq!("as", Vec::<String>::new().to_type_code());
+    }
+
+    #[test]
+    fn dbus_type_const_signature() {
+        const SIGNATURE: &str = <(u8, Vec<(String, Variant)>)>::SIGNATURE;
+        assert_eq!("(ya(sv))", SIGNATURE);
+        assert_eq!(
+            "a{sa{sv}}",
+            BTreeMap::<String, HashMap<String, Variant>>::SIGNATURE
+        );
+    }
+
+    #[test]
+    fn dbus_type_signature() {
+        assert_eq!(Signature("ay".into()), Vec::<u8>::signature());
+        assert_eq!(
+            Signature("a{sv}".into()),
+            HashMap::<String, Variant>::signature()
+        );
+        assert_eq!(
+            Signature("(oa{sa{sv}})".into()),
+            <(ObjectPath, BTreeMap<String, HashMap<String, Variant>>)>::signature()
+        );
+        // The signature of a type is not allocated
+        assert!(matches!(Vec::<u8>::signature().0, Cow::Borrowed("ay")));
     }
 
     #[test]
@@ -39,11 +85,454 @@ mod tests {
         let mut hmap = HashMap::new();
         hmap.insert(1u8, "Value_1".to_string());
         hmap.insert(2u8, "Value_2".to_string());
-        assert_eq!("{ys}", hmap.to_type_code());
+        assert_eq!("a{ys}", hmap.to_type_code());
+    }
+
+    #[test]
+    fn serial() {
+        assert!(Serial::try_from(0).is_err());
+        assert_eq!(1, Serial::FIRST.get());
+        assert_eq!(2, Serial::FIRST.next().get());
+
+        let last = Serial::try_from(u32::MAX).unwrap();
+        assert_eq!(Serial::FIRST, last.next());
+    }
+
+    #[test]
+    fn value_conversion_basic_types() {
+        assert_eq!(Ok(1u8), u8::try_from(Value::from(1u8)));
+        assert_eq!(Ok(true), bool::try_from(Value::from(true)));
+        assert_eq!(Ok(-20i32), i32::try_from(Value::from(-20i32)));
+        assert_eq!(Ok("abc".to_string()), String::try_from(Value::from("abc")));
+        assert_eq!(
+            Err(ValueError::UnexpectedType),
+            u32::try_from(Value::from(20i32))
+        );
+    }
+
+    #[test]
+    fn value_conversion_containers() {
+        let vec = vec![1u16, 2u16];
+        assert_eq!(Ok(vec.clone()), Vec::<u16>::try_from(Value::from(vec)));
+
+        let mut hmap = HashMap::new();
+        hmap.insert(1u8, "Value_1".to_string());
+        hmap.insert(2u8, "Value_2".to_string());
+        assert_eq!(
+            Ok(hmap.clone()),
+            HashMap::<u8, String>::try_from(Value::from(hmap))
+        );
+
+        let tuple = (1u8, "abc".to_string(), 3.5f64);
+        assert_eq!(
+            Ok(tuple.clone()),
+            <(u8, String, f64)>::try_from(Value::from(tuple))
+        );
+        assert_eq!(
+            Err(ValueError::UnexpectedStructLength {
+                expected: 2,
+                found: 3
+            }),
+            <(u8, String)>::try_from(Value::from((1u8, "abc", 3.5f64)))
+        );
+    }
+
+    #[test]
+    fn value_conversion_variant() {
+        let v = Value::variant_of(7u32).unwrap();
+        let expected = Variant::from_parts(Signature("u".into()), Value::Uint32(7));
+        assert_eq!(Value::Variant(Box::new(expected)), v);
+        assert_eq!(Ok(v.clone()), Value::Uint32(7).into_variant());
+        assert_eq!(v, Value::from(Variant::new(7u32).unwrap()));
+
+        let variant = Variant::try_from(v).unwrap();
+        assert_eq!(Value::Uint32(7), *variant);
+        assert_eq!("v", variant.to_type_code());
+        assert_eq!(
+            Err(ValueError::UnexpectedType),
+            Variant::try_from(Value::Uint32(7))
+        );
+
+        let mut props = HashMap::new();
+        props.insert("Volume".to_string(), Variant::new(50u8).unwrap());
+        let props = HashMap::<String, Variant>::try_from(Value::from(props)).unwrap();
+        assert_eq!(Ok(50u8), props["Volume"].clone().downcast());
+    }
+
+    #[test]
+    fn variant() {
+        let variant = Variant::new((1u8, vec![2u32])).unwrap();
+        assert_eq!(&Signature("(yau)".into()), variant.signature());
+        assert_eq!(
+            Err(ValueError::UnexpectedType),
+            variant.clone().downcast::<u8>()
+        );
+        assert_eq!(Ok((1u8, vec![2u32])), variant.clone().downcast());
+        assert_eq!(
+            Err(ValueError::UnknownElementType),
+            Variant::new(Vec::<u8>::new())
+        );
+        let empty = Variant::of(Vec::<String>::new());
+        assert_eq!(&Signature("as".into()), empty.signature());
+
+        // The signature of an empty array is kept, whether the variant is written on its own
+        // or as a value.
+        for value in [Value::from(empty.clone()), Value::from((empty.clone(),))].iter() {
+            let mut buffer = Vec::new();
+            let mut writer = DbusWriter::new(&mut buffer, Endianness::Little);
+            value.write(&mut writer).unwrap();
+            let signature = value.signature().unwrap();
+            let mut reader = DbusReader::new(buffer.as_slice(), Endianness::Little);
+            assert_eq!(
+                vec![value.clone()],
+                Value::read_all(&mut reader, &signature).unwrap()
+            );
+        }
+
+        let mut buffer = Vec::new();
+        let mut writer = DbusWriter::new(&mut buffer, Endianness::Little);
+        variant.write(&mut writer).unwrap();
+        assert_eq!(
+            vec![5, b'(', b'y', b'a', b'u', b')', 0, 0, 1, 0, 0, 0, 4, 0, 0, 0, 2, 0, 0, 0],
+            buffer
+        );
+
+        let mut reader = DbusReader::new(buffer.as_slice(), Endianness::Little);
+        assert_eq!(variant, Variant::read(&mut reader).unwrap());
+    }
+
+    #[test]
+    fn typed_variant() {
+        let mut volume = TypedVariant(50u8);
+        *volume += 1;
+        assert_eq!(51, *volume);
+        assert_eq!("v", volume.to_type_code());
+        assert_eq!(
+            Value::variant_of(51u8).unwrap(),
+            Value::from(volume.clone())
+        );
+
+        let mut props = HashMap::new();
+        props.insert("Volume".to_string(), volume.clone());
+        let props = HashMap::<String, TypedVariant<u8>>::try_from(Value::from(props)).unwrap();
+        assert_eq!(51, *props["Volume"]);
+        assert_eq!(
+            Err(ValueError::UnexpectedType),
+            TypedVariant::<u16>::try_from(Value::variant_of(51u8).unwrap())
+        );
+        assert_eq!(
+            Err(ValueError::UnexpectedType),
+            TypedVariant::<u8>::try_from(Value::Byte(51))
+        );
+
+        // Empty dicts keep their signature
+        let empty = TypedVariant(HashMap::<String, u32>::new());
+        let variant = Variant::from(empty.clone());
+        assert_eq!(&Signature("a{su}".into()), variant.signature());
+        assert_eq!(Ok(empty.clone()), TypedVariant::try_from(variant.clone()));
+
+        let mut buffer = Vec::new();
+        let mut writer = DbusWriter::new(&mut buffer, Endianness::Little);
+        empty.write(&mut writer).unwrap();
+        let mut written = Vec::new();
+        let mut writer = DbusWriter::new(&mut written, Endianness::Little);
+        variant.write(&mut writer).unwrap();
+        assert_eq!(written, buffer);
+
+        let mut reader = DbusReader::new(buffer.as_slice(), Endianness::Little);
+        assert_eq!(empty, TypedVariant::read(&mut reader).unwrap());
+        let mut reader = DbusReader::new(buffer.as_slice(), Endianness::Little);
+        assert!(TypedVariant::<HashMap<String, u8>>::read(&mut reader).is_err());
+    }
+
+    #[test]
+    fn tuple_marshal_unmarshal() {
+        let tuple = (1u8, (2u16, true), -3i64);
+        assert_eq!("(y(qb)x)", tuple.to_type_code());
+
+        let mut buffer = Vec::new();
+        let mut writer = DbusWriter::new(&mut buffer, Endianness::Little);
+        let len = 7u8.write(&mut writer).unwrap();
+        let len = len + tuple.write(&mut writer).unwrap();
+        assert_eq!(buffer.len() as u64, len);
+        assert_eq!(
+            vec![
+                7, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 253, 255,
+                255, 255, 255, 255, 255, 255
+            ],
+            buffer
+        );
+
+        let mut reader = DbusReader::new(buffer.as_slice(), Endianness::Little);
+        assert_eq!(7, u8::read(&mut reader).unwrap());
+        assert_eq!(tuple, <(u8, (u16, bool), i64)>::read(&mut reader).unwrap());
+    }
+
+    #[test]
+    fn dict_marshal_unmarshal() {
+        let mut map = BTreeMap::new();
+        map.insert(1u8, (2u16, 3u32));
+        map.insert(4u8, (5u16, 6u32));
+        assert_eq!("a{y(qu)}", map.to_type_code());
+
+        let mut buffer = Vec::new();
+        let mut writer = DbusWriter::new(&mut buffer, Endianness::Little);
+        let len = 7u8.write(&mut writer).unwrap();
+        let len = len + map.write(&mut writer).unwrap();
+        assert_eq!(buffer.len() as u64, len);
+        assert_eq!(
+            vec![
+                7, 0, 0, 0, 32, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0,
+                0, 0, 0, 0, 0, 5, 0, 0, 0, 6, 0, 0, 0
+            ],
+            buffer
+        );
+
+        let mut reader = DbusReader::new(buffer.as_slice(), Endianness::Little);
+        assert_eq!(7, u8::read(&mut reader).unwrap());
+        assert_eq!(map, BTreeMap::<u8, (u16, u32)>::read(&mut reader).unwrap());
+
+        let mut reader = DbusReader::new(buffer.as_slice(), Endianness::Little);
+        reader.read_u8().unwrap();
+        let hmap = HashMap::<u8, (u16, u32)>::read(&mut reader).unwrap();
+        assert_eq!(Some(&(5, 6)), hmap.get(&4));
+        assert_eq!("a{y(qu)}", hmap.to_type_code());
+    }
+
+    #[test]
+    fn signature_compatibility() {
+        let compatible = |declared: &str, actual: &str| {
+            Signature(declared.to_string().into())
+                .is_compatible_with(&Signature(actual.to_string().into()))
+        };
+        assert!(compatible("", ""));
+        assert!(compatible("sa{sv}as", "sa{sv}as"));
+        assert!(compatible("v", "a(ii)"));
+        assert!(compatible("a{sv}", "a{sai}"));
+        assert!(compatible("(vy)", "((yy)y)"));
+        assert!(!compatible("s", "o"));
+        assert!(!compatible("s", "ss"));
+        assert!(!compatible("ss", "s"));
+        assert!(!compatible("v", ""));
+        assert!(!compatible("v", "a"));
+        assert!(!compatible("a{sv}", "a{sv"));
+        assert!(!compatible("i", "v"));
+    }
+
+    #[test]
+    fn value_signature() {
+        let mut map = HashMap::new();
+        map.insert(1u8, Value::variant_of(2.5f64).unwrap());
+        let v = Value::from((7u8, vec![-1i64], map));
+        assert_eq!(Ok(Signature("(yaxa{yv})".into())), v.signature());
+
+        assert_eq!(
+            Err(ValueError::UnknownElementType),
+            Value::Array(Vec::new()).signature()
+        );
+        assert_eq!(
+            Err(ValueError::UnexpectedType),
+            Value::Array(vec![Value::from(1u8), Value::from(1u16)]).signature()
+        );
+    }
+
+    #[test]
+    fn value_marshal_unmarshal() {
+        let mut map = HashMap::new();
+        map.insert(1u8, Value::variant_of(2.5f64).unwrap());
+        let values = vec![
+            Value::from(7u8),
+            Value::from((true, vec![-1i64, 2i64], map)),
+            Value::variant_of(Value::from((3u16, UnixFd(4)))).unwrap(),
+        ];
+
+        let mut buffer = Vec::new();
+        let mut writer = DbusWriter::new(&mut buffer, Endianness::Little);
+        let mut bytes_written = 0;
+        for v in values.iter() {
+            bytes_written += v.write(&mut writer).unwrap();
+        }
+        assert_eq!(buffer.len() as u64, bytes_written);
+        assert_eq!(
+            vec![
+                7, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 16, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255,
+                255, 2, 0, 0, 0, 0, 0, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0, 1, 1, b'd', 0, 0, 0, 0, 0, 0,
+                0, 0, 0, 0, 0, 4, 64, 4, b'(', b'q', b'h', b')', 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0
+            ],
+            buffer
+        );
+
+        let signature = Signature("y(baxa{yv})v".into());
+        let mut reader = DbusReader::new(buffer.as_slice(), Endianness::Little);
+        assert_eq!(values, Value::read_all(&mut reader, &signature).unwrap());
+    }
+
+    #[test]
+    fn signature_validation() {
+        let valid = [
+            "",
+            "y",
+            "a{sv}as",
+            "(i(ss)av)",
+            "a{oa{sa{sv}}}",
+            "aay",
+            "ha{yh}",
+        ];
+        for signature in valid.iter() {
+            assert_eq!(Ok(()), Signature(signature.to_string().into()).validate());
+        }
+
+        let invalid = [
+            ("z", SignatureError::InvalidTypeCode('z')),
+            ("r", SignatureError::InvalidTypeCode('r')),
+            ("s\0", SignatureError::InvalidTypeCode('\0')),
+            ("a", SignatureError::EndsWithinCompleteType),
+            ("(ss", SignatureError::EndsWithinCompleteType),
+            ("a{sv", SignatureError::EndsWithinCompleteType),
+            ("s)", SignatureError::UnexpectedClose(')')),
+            ("(a)", SignatureError::UnexpectedClose(')')),
+            ("()", SignatureError::EmptyStruct),
+            ("{sv}", SignatureError::DictEntryOutsideArray),
+            ("(a{sv}{sv})", SignatureError::DictEntryOutsideArray),
+            ("a{vs}", SignatureError::DictEntryKeyNotBasic),
+            ("a{(y)s}", SignatureError::DictEntryKeyNotBasic),
+            ("a{s}", SignatureError::DictEntryFieldCount),
+            ("a{}", SignatureError::DictEntryFieldCount),
+            ("a{sss}", SignatureError::DictEntryFieldCount),
+        ];
+        for (signature, expected) in invalid.iter() {
+            assert_eq!(
+                Err(*expected),
+                Signature(signature.to_string().into()).validate(),
+                "{}",
+                signature
+            );
+        }
+
+        assert_eq!(
+            Err(SignatureError::ExceedsMaxLength),
+            Signature::try_from("y".repeat(256).as_str())
+        );
+        assert_eq!(Ok(Signature("a{sv}".into())), Signature::try_from("a{sv}"));
+    }
+
+    #[test]
+    fn object_path_validate() {
+        let valid = ["/", "/a", "/org/freedesktop/DBus", "/_/A_1/z9"];
+        for path in valid.iter() {
+            assert_eq!(Ok(()), ObjectPath(path.to_string()).validate(), "{}", path);
+        }
+
+        let invalid = [
+            ("", ObjectPathError::MissingLeadingSlash),
+            ("a/b", ObjectPathError::MissingLeadingSlash),
+            ("/a/", ObjectPathError::TrailingSlash),
+            ("//", ObjectPathError::TrailingSlash),
+            ("//a", ObjectPathError::EmptyElement),
+            ("/a//b", ObjectPathError::EmptyElement),
+            ("/a-b", ObjectPathError::InvalidCharacter('-')),
+            ("/a.b", ObjectPathError::InvalidCharacter('.')),
+            ("/\u{e4}", ObjectPathError::InvalidCharacter('\u{e4}')),
+        ];
+        for (path, expected) in invalid.iter() {
+            assert_eq!(
+                Err(*expected),
+                ObjectPath(path.to_string()).validate(),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn signature_nesting() {
+        let nested = |prefix: &str, n: usize, suffix: &str| {
+            Signature(format!("{}y{}", prefix.repeat(n), suffix.repeat(n)).into()).validate()
+        };
+        assert_eq!(Ok(()), nested("a", 32, ""));
+        assert_eq!(
+            Err(SignatureError::Nesting(NestingError::Arrays)),
+            nested("a", 33, "")
+        );
+        assert_eq!(Ok(()), nested("(", 32, ")"));
+        assert_eq!(
+            Err(SignatureError::Nesting(NestingError::Structs)),
+            nested("(", 33, ")")
+        );
+        assert_eq!(Ok(()), nested("a(", 32, ")"));
+        assert_eq!(
+            Ok(()),
+            Signature(format!("{}y", "a".repeat(32)).repeat(2).into()).validate()
+        );
+    }
+
+    #[test]
+    fn signature_complete_types() {
+        let signature = Signature("ya{sv}(ai)v".into());
+        assert_eq!(
+            vec!["y", "a{sv}", "(ai)", "v"],
+            signature.complete_types().collect::<Vec<_>>()
+        );
+        let signature = Signature("sa(".into());
+        assert_eq!(vec!["s"], signature.complete_types().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn value_unmarshal_nesting_depth() {
+        // Variants nest without any limit in the signature, only the total depth bounds them
+        let mut buffer = Vec::new();
+        for _ in 0..MAX_SIGNATURE_DEPTH + 1 {
+            buffer.extend_from_slice(&[1, b'v', 0]);
+        }
+        let mut reader = DbusReader::new(buffer.as_slice(), Endianness::Little);
+        let err = Value::read(&mut reader, &Signature("v".into())).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!(
+            Some(&NestingError::Total),
+            err.get_ref().and_then(|e| e.downcast_ref::<NestingError>())
+        );
+    }
+
+    #[test]
+    fn value_unmarshal_invalid_signature() {
+        let buffer = [0u8; 16];
+        for signature in &["a", "(", "()", "(y", "{yy", "{yyy}", "z"] {
+            let mut reader = DbusReader::new(&buffer[..], Endianness::Little);
+            let signature = Signature(signature.to_string().into());
+            assert!(Value::read_all(&mut reader, &signature).is_err());
+        }
+    }
+
+    #[test]
+    fn value_unmarshal_byte_array() {
+        let buffer = [3, 0, 0, 0, 1, 2, 3, 0, 1, 0, 0, 0, 4];
+        let mut reader = DbusReader::new(&buffer[..], Endianness::Little);
+        let signature = Signature("ayay".into());
+        let values = Value::read_all(&mut reader, &signature).unwrap();
+        assert_eq!(
+            vec![Value::ByteArray(vec![1, 2, 3]), Value::ByteArray(vec![4])],
+            values
+        );
+        assert_eq!(Signature("ay".into()), values[0].signature().unwrap());
+        assert_eq!(Ok(vec![1u8, 2, 3]), Vec::<u8>::try_from(values[0].clone()));
+
+        let mut written = Vec::new();
+        let mut writer = DbusWriter::new(&mut written, Endianness::Little);
+        values[0].write(&mut writer).unwrap();
+        values[1]
+            .write_with_signature(&mut writer, &Signature("ay".into()))
+            .unwrap();
+        assert_eq!(&buffer[..], written.as_slice());
+
+        // The array length is checked before the bytes are read
+        let buffer = [3, 0, 0, 0, 1, 2];
+        let mut reader = DbusReader::new(&buffer[..], Endianness::Little);
+        let err = Value::read(&mut reader, &Signature("ay".into())).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
     }
 }
 
-pub type TypeCode = String;
+pub type TypeCode = &'static str;
 
 /// Marker type for DictEntry enforcing that only basic types can act as key.
 /// >  The first single complete type (the "key") must be a basic type rather than a container type.
@@ -68,31 +557,416 @@ pub trait ToTypeCode: Sized {
     fn to_type_code(&self) -> TypeCode;
 }
 
+/// The D-Bus type of a Rust type. Unlike `ToTypeCode` the signature only depends on the
+/// type, so it is known even without a value at hand, e.g. for the elements of an empty `Vec`.
+pub trait DbusType {
+    /// The signature of the single complete type values of this type are marshaled as.
+    /// Signatures of containers are assembled at compile time using `ConstSignature`.
+    const SIGNATURE: &'static str;
+
+    fn signature() -> Signature {
+        Signature(Cow::Borrowed(Self::SIGNATURE))
+    }
+}
+
+impl<T: DbusType> ToTypeCode for T {
+    fn to_type_code(&self) -> TypeCode {
+        T::SIGNATURE
+    }
+}
+
+/// Assembles the signature of a container type from the signatures of the contained types
+/// in const evaluation, e.g. `ConstSignature::new("a").append(T::SIGNATURE).as_str()`.
+/// Exceeding the maximum signature length fails the compilation.
+#[derive(Clone, Copy, Debug)]
+pub struct ConstSignature {
+    bytes: [u8; MAX_SIGNATURE_LENGTH],
+    len: usize,
+}
+
+// Only evaluated at compile time, where a panic fails the compilation.
+#[allow(
+    clippy::arithmetic_side_effects,
+    clippy::indexing_slicing,
+    clippy::panic
+)]
+impl ConstSignature {
+    pub const fn new(s: &str) -> ConstSignature {
+        ConstSignature {
+            bytes: [0; MAX_SIGNATURE_LENGTH],
+            len: 0,
+        }
+        .append(s)
+    }
+
+    /// This signature followed by `s`.
+    pub const fn append(mut self, s: &str) -> ConstSignature {
+        let bytes = s.as_bytes();
+        assert!(
+            self.len + bytes.len() <= MAX_SIGNATURE_LENGTH,
+            "Signature exceeds maximum length"
+        );
+        let mut i = 0;
+        while i < bytes.len() {
+            self.bytes[self.len + i] = bytes[i];
+            i += 1;
+        }
+        self.len += bytes.len();
+        self
+    }
+
+    pub const fn as_str(&self) -> &str {
+        // Only whole strs are appended, so the bytes are always valid UTF-8
+        match std::str::from_utf8(self.bytes.split_at(self.len).0) {
+            Ok(s) => s,
+            Err(_) => panic!("Signature is not valid UTF-8"),
+        }
+    }
+}
+
 /// The serial of this message, used as a cookie by the sender to identify
 /// the reply corresponding to this request. This must not be zero.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub struct Serial(pub u32);
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Serial(NonZeroU32);
+
+impl Serial {
+    /// The first serial used by a connection.
+    pub const FIRST: Serial = Serial(NonZeroU32::MIN);
+
+    pub fn get(self) -> u32 {
+        self.0.get()
+    }
+
+    /// The serial following this one, wrapping around to `Serial::FIRST`
+    /// instead of zero once `u32::MAX` is reached.
+    pub fn next(self) -> Serial {
+        match NonZeroU32::new(self.0.get().wrapping_add(1)) {
+            Some(s) => Serial(s),
+            None => Serial::FIRST,
+        }
+    }
+}
 
 impl TryFrom<u32> for Serial {
     type Error = io::Error;
 
     fn try_from(s: u32) -> io::Result<Serial> {
-        if s == 0 {
-            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        NonZeroU32::new(s)
+            .map(Serial)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Serial must not be zero"))
+    }
+}
+
+impl From<Serial> for u32 {
+    fn from(s: Serial) -> u32 {
+        s.get()
+    }
+}
+
+/// A single complete value of any type along with its signature, e.g. the value of a property or of an `a{sv}` dict.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Variant {
+    signature: Signature,
+    value: Value,
+}
+
+impl Variant {
+    /// Wraps `value`, failing if its signature can not be derived.
+    pub fn new<T: Into<Value>>(value: T) -> Result<Variant, ValueError> {
+        let value = value.into();
+        let signature = value.signature()?;
+        Ok(Variant { signature, value })
+    }
+
+    /// Wraps `value` with the signature of its type, which unlike `new` also works for empty arrays.
+    pub fn of<T: DbusType + Into<Value>>(value: T) -> Variant {
+        Variant {
+            signature: T::signature(),
+            value: value.into(),
+        }
+    }
+
+    /// Wraps `value` read with `signature`, keeping the signature as received
+    /// even where it can not be derived from the value, e.g. for empty arrays.
+    pub(crate) fn from_parts(signature: Signature, value: Value) -> Variant {
+        Variant { signature, value }
+    }
+
+    /// The signature of the single complete type of the contained value.
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    pub fn into_inner(self) -> Value {
+        self.value
+    }
+
+    /// Converts the contained value into `T`, failing if it holds a different type.
+    pub fn downcast<T: TryFrom<Value, Error = ValueError>>(self) -> Result<T, ValueError> {
+        T::try_from(self.value)
+    }
+}
+
+impl Deref for Variant {
+    type Target = Value;
+
+    fn deref(&self) -> &Value {
+        &self.value
+    }
+}
+
+impl From<Variant> for Value {
+    fn from(v: Variant) -> Value {
+        Value::Variant(Box::new(v))
+    }
+}
+
+impl TryFrom<Value> for Variant {
+    type Error = ValueError;
+
+    fn try_from(v: Value) -> Result<Variant, ValueError> {
+        match v {
+            Value::Variant(v) => Ok(*v),
+            _ => Err(ValueError::UnexpectedType),
         }
-        Ok(Serial(s))
     }
 }
 
-struct Variant {}
+impl DbusWrite for Variant {
+    fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64, io::Error> {
+        writer.write_variant_with(self.signature.clone(), |writer| {
+            self.value.write_with_signature(writer, &self.signature)
+        })
+    }
+}
+
+impl DbusRead for Variant {
+    fn read(reader: &mut DbusReader<dyn io::Read + '_>) -> Result<Variant, io::Error> {
+        let signature = reader.read_signature()?;
+        let value = Value::read(reader, &signature)?;
+        Ok(Variant { signature, value })
+    }
+}
 
 /// VARIANT has ASCII character 'v' as its type code.
 /// A marshaled value of type VARIANT will have the signature of a single complete type as part of the value.
 /// This signature will be followed by a marshaled value of that type.
-impl ToTypeCode for Variant {
-    fn to_type_code(&self) -> TypeCode {
-        "v".to_string()
-        // TODO add remaining variants ?
+impl DbusType for Variant {
+    const SIGNATURE: &'static str = "v";
+}
+
+/// A value of type `T` wrapped in a VARIANT, for variants whose type is known up front,
+/// e.g. a property of a known type. Unlike `Variant` it dereferences to the `T` itself.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypedVariant<T: DbusType>(pub T);
+
+impl<T: DbusType> TypedVariant<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: DbusType> Deref for TypedVariant<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DbusType> DerefMut for TypedVariant<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: DbusType + Into<Value>> From<TypedVariant<T>> for Variant {
+    fn from(v: TypedVariant<T>) -> Variant {
+        Variant::of(v.0)
+    }
+}
+
+impl<T: DbusType + Into<Value>> From<TypedVariant<T>> for Value {
+    fn from(v: TypedVariant<T>) -> Value {
+        Value::from(Variant::from(v))
+    }
+}
+
+/// Fails unless the variant holds a value of the signature of `T`.
+impl<T> TryFrom<Variant> for TypedVariant<T>
+where
+    T: DbusType + TryFrom<Value, Error = ValueError>,
+{
+    type Error = ValueError;
+
+    fn try_from(v: Variant) -> Result<TypedVariant<T>, ValueError> {
+        if v.signature.0 != T::SIGNATURE {
+            return Err(ValueError::UnexpectedType);
+        }
+        v.downcast().map(TypedVariant)
+    }
+}
+
+impl<T> TryFrom<Value> for TypedVariant<T>
+where
+    T: DbusType + TryFrom<Value, Error = ValueError>,
+{
+    type Error = ValueError;
+
+    fn try_from(v: Value) -> Result<TypedVariant<T>, ValueError> {
+        TypedVariant::try_from(Variant::try_from(v)?)
+    }
+}
+
+impl<T: DbusType + DbusWrite> DbusWrite for TypedVariant<T> {
+    fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64, io::Error> {
+        writer.write_variant_with(T::signature(), |writer| self.0.write(writer))
+    }
+}
+
+impl<T: DbusType + DbusRead> DbusRead for TypedVariant<T> {
+    fn read(reader: &mut DbusReader<dyn io::Read + '_>) -> Result<TypedVariant<T>, io::Error> {
+        let signature = reader.read_signature()?;
+        if signature.0 != T::SIGNATURE {
+            let str_err = format!(
+                "Variant signature `{}` does not match `{}`",
+                signature.0,
+                T::SIGNATURE
+            );
+            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+        }
+        T::read(reader).map(TypedVariant)
+    }
+}
+
+impl<T: DbusType> DbusType for TypedVariant<T> {
+    const SIGNATURE: &'static str = "v";
+}
+
+/// Returned when string data is malformed, wrapped in an `io::Error`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StringError {
+    /// Strings must not contain nul bytes, the nul byte marks the end of the string on the wire.
+    InteriorNul,
+
+    /// The string data is not followed by a terminating nul byte.
+    MissingNulTerminator,
+
+    /// Strings must be valid UTF-8.
+    InvalidUtf8,
+
+    /// The string is longer than a message may be.
+    ExceedsMaxSize,
+}
+
+impl fmt::Display for StringError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StringError::InteriorNul => write!(f, "String contains a nul byte"),
+            StringError::MissingNulTerminator => write!(f, "String is not nul terminated"),
+            StringError::InvalidUtf8 => write!(f, "String is not valid UTF-8"),
+            StringError::ExceedsMaxSize => write!(f, "String exceeds maximum message size"),
+        }
+    }
+}
+
+impl std::error::Error for StringError {}
+
+/// Returned when containers are nested deeper than the specification allows, wrapped in an `io::Error`.
+/// Bounding the nesting also bounds the recursion when unmarshaling hostile input.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NestingError {
+    /// More than `MAX_ARRAY_NESTING_DEPTH` arrays are nested.
+    Arrays,
+
+    /// More than `MAX_STRUCT_NESTING_DEPTH` structs or dict entries are nested.
+    Structs,
+
+    /// Containers including variants are nested deeper than `MAX_SIGNATURE_DEPTH` in total.
+    Total,
+}
+
+impl fmt::Display for NestingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NestingError::Arrays => write!(
+                f,
+                "Arrays exceed the maximum nesting depth of {}",
+                MAX_ARRAY_NESTING_DEPTH
+            ),
+            NestingError::Structs => write!(
+                f,
+                "Structs exceed the maximum nesting depth of {}",
+                MAX_STRUCT_NESTING_DEPTH
+            ),
+            NestingError::Total => write!(
+                f,
+                "Containers exceed the maximum nesting depth of {}",
+                MAX_SIGNATURE_DEPTH
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NestingError {}
+
+impl From<NestingError> for io::Error {
+    fn from(err: NestingError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// The containers enclosing a value, counted separately as the specification limits them separately.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ContainerDepth {
+    arrays: usize,
+    structs: usize,
+    variants: usize,
+}
+
+impl ContainerDepth {
+    /// The depth of the elements of an array at this depth.
+    pub(crate) fn array(self) -> Result<ContainerDepth, NestingError> {
+        if self.arrays >= MAX_ARRAY_NESTING_DEPTH {
+            return Err(NestingError::Arrays);
+        }
+        ContainerDepth {
+            arrays: self.arrays.saturating_add(1),
+            ..self
+        }
+        .check_total()
+    }
+
+    /// The depth of the fields of a struct or dict entry at this depth.
+    pub(crate) fn structure(self) -> Result<ContainerDepth, NestingError> {
+        if self.structs >= MAX_STRUCT_NESTING_DEPTH {
+            return Err(NestingError::Structs);
+        }
+        ContainerDepth {
+            structs: self.structs.saturating_add(1),
+            ..self
+        }
+        .check_total()
+    }
+
+    /// The depth of the value of a variant at this depth.
+    pub(crate) fn variant(self) -> Result<ContainerDepth, NestingError> {
+        ContainerDepth {
+            variants: self.variants.saturating_add(1),
+            ..self
+        }
+        .check_total()
+    }
+
+    fn check_total(self) -> Result<ContainerDepth, NestingError> {
+        let total = self
+            .arrays
+            .saturating_add(self.structs)
+            .saturating_add(self.variants);
+        if total > MAX_SIGNATURE_DEPTH {
+            return Err(NestingError::Total);
+        }
+        Ok(self)
     }
 }
 
@@ -106,189 +980,1162 @@ pub struct ObjectPath(pub String);
 // TODO impl from str for ObjectPath see "Valid Object Paths"
 
 impl DbusWrite for ObjectPath {
-    fn write<T1, T2>(
-        &self,
-        writer: &mut DbusWriter<T1>,
-        bytes_written: u64,
-    ) -> Result<u64, io::Error>
-    where
-        T1: io::Write,
-        T2: ByteOrder,
-    {
-        writer.write_string::<T2>(&self.0, bytes_written)
+    fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64, io::Error> {
+        writer.write_string(&self.0)
     }
 }
 
 /// based on "Basic type" - Table
-impl ToTypeCode for ObjectPath {
-    fn to_type_code(&self) -> TypeCode {
-        "o".to_string()
+impl DbusType for ObjectPath {
+    const SIGNATURE: &'static str = "o";
+}
+
+impl ObjectPath {
+    /// Checks the rules of "Valid Object Paths": the path begins with a `/` followed by
+    /// elements separated by `/`, each made up of the ASCII characters `[A-Z][a-z][0-9]_`.
+    /// No element may be empty and only the root path `/` may end with a `/`.
+    pub fn validate(&self) -> Result<(), ObjectPathError> {
+        let elements = match self.0.strip_prefix('/') {
+            Some("") => return Ok(()),
+            Some(elements) => elements,
+            None => return Err(ObjectPathError::MissingLeadingSlash),
+        };
+        if elements.ends_with('/') {
+            return Err(ObjectPathError::TrailingSlash);
+        }
+        for element in elements.split('/') {
+            if element.is_empty() {
+                return Err(ObjectPathError::EmptyElement);
+            }
+            if let Some(c) = element
+                .chars()
+                .find(|c| !c.is_ascii_alphanumeric() && *c != '_')
+            {
+                return Err(ObjectPathError::InvalidCharacter(c));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returned when an object path is malformed, wrapped in an `io::Error`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ObjectPathError {
+    /// Object paths must begin with a `/`.
+    MissingLeadingSlash,
+
+    /// Only the root path `/` may end with a `/`.
+    TrailingSlash,
+
+    /// Elements must not be empty, i.e. paths must not contain `//`.
+    EmptyElement,
+
+    /// Elements must only contain the ASCII characters `[A-Z][a-z][0-9]_`.
+    InvalidCharacter(char),
+}
+
+impl fmt::Display for ObjectPathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjectPathError::MissingLeadingSlash => write!(f, "Object path must begin with `/`"),
+            ObjectPathError::TrailingSlash => write!(f, "Object path must not end with `/`"),
+            ObjectPathError::EmptyElement => write!(f, "Object path contains an empty element"),
+            ObjectPathError::InvalidCharacter(c) => write!(
+                f,
+                "Invalid character `{}` in object path",
+                c.escape_default()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ObjectPathError {}
+
+impl From<ObjectPathError> for io::Error {
+    fn from(err: ObjectPathError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
     }
 }
 
 /// The same as STRING except the length is a single byte
 /// (thus signatures have a maximum length of 255) and the
 /// content must be a valid signature (see above).
+/// Signatures of Rust types are borrowed from `DbusType::SIGNATURE` instead of allocated.
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Signature(pub String);
+pub struct Signature(pub Cow<'static, str>);
+
+impl Signature {
+    /// Alignment in bytes of a value of the first complete type in this signature,
+    /// based on the "Alignment" column of the marshaling table.
+    pub fn alignment(&self) -> u64 {
+        self.0.chars().next().map_or(1, Signature::alignment_of)
+    }
+
+    /// Whether values of signature `other` may be used where this signature is declared.
+    /// The types must match exactly, except that a VARIANT in this signature accepts
+    /// any single complete type at the same position in `other`.
+    pub fn is_compatible_with(&self, other: &Signature) -> bool {
+        let mut declared = self.0.as_bytes();
+        let mut actual = other.0.as_bytes();
+        while let Some((type_code, rest)) = declared.split_first() {
+            actual = match (type_code, actual.split_first()) {
+                (b'v', _) => match skip_complete_type(actual) {
+                    Ok(rest_actual) => rest_actual,
+                    Err(_) => return false,
+                },
+                (_, Some((actual_type_code, rest_actual))) if actual_type_code == type_code => {
+                    rest_actual
+                }
+                _ => return false,
+            };
+            declared = rest;
+        }
+        actual.is_empty()
+    }
+
+    /// Fails unless this is a valid signature: a sequence of single complete types
+    /// made up of known type codes, not exceeding the maximum length and nesting depth.
+    pub fn validate(&self) -> Result<(), SignatureError> {
+        if self.0.len() > MAX_SIGNATURE_LENGTH {
+            return Err(SignatureError::ExceedsMaxLength);
+        }
+        let mut types = self.0.as_bytes();
+        while !types.is_empty() {
+            types = parse_complete_type(types, ContainerDepth::default(), false)?;
+        }
+        Ok(())
+    }
+
+    /// The single complete types this signature is made up of, e.g. the type of each argument
+    /// of a message body. Iteration stops at the first malformed type of an invalid signature.
+    pub fn complete_types(&self) -> CompleteTypes<'_> {
+        CompleteTypes { types: &self.0 }
+    }
+
+    /// Alignment in bytes of a value starting with the given type code.
+    pub fn alignment_of(type_code: char) -> u64 {
+        match type_code {
+            'n' | 'q' => 2,
+            'b' | 'i' | 'u' | 'h' | 's' | 'o' | 'a' => 4,
+            'x' | 't' | 'd' | '(' | '{' => 8,
+            _ => 1,
+        }
+    }
+}
+
+impl TryFrom<&str> for Signature {
+    type Error = SignatureError;
+
+    fn try_from(s: &str) -> Result<Signature, SignatureError> {
+        let signature = Signature(s.to_string().into());
+        signature.validate()?;
+        Ok(signature)
+    }
+}
+
+impl TryFrom<String> for Signature {
+    type Error = SignatureError;
+
+    fn try_from(s: String) -> Result<Signature, SignatureError> {
+        let signature = Signature(s.into());
+        signature.validate()?;
+        Ok(signature)
+    }
+}
+
+/// Iterator over the single complete types of a signature, see `Signature::complete_types`.
+#[derive(Clone, Debug)]
+pub struct CompleteTypes<'a> {
+    types: &'a str,
+}
+
+impl<'a> Iterator for CompleteTypes<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        if self.types.is_empty() {
+            return None;
+        }
+        let split = skip_complete_type(self.types.as_bytes())
+            .ok()
+            .and_then(|rest| {
+                let len = self.types.len().saturating_sub(rest.len());
+                Some((self.types.get(..len)?, self.types.get(len..)?))
+            });
+        match split {
+            Some((complete_type, rest)) => {
+                self.types = rest;
+                Some(complete_type)
+            }
+            None => {
+                self.types = "";
+                None
+            }
+        }
+    }
+}
+
+/// Returned when a signature is malformed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SignatureError {
+    /// Signatures have a maximum length of 255.
+    ExceedsMaxLength,
+
+    /// The signature contains a character that is not a type code.
+    InvalidTypeCode(char),
+
+    /// The signature ends before the last complete type does, e.g. an array without element type.
+    EndsWithinCompleteType,
+
+    /// A closing parenthesis or brace without a matching opening one.
+    UnexpectedClose(char),
+
+    /// Structs must have at least one field.
+    EmptyStruct,
+
+    /// Dict entries must only appear as the element type of an array.
+    DictEntryOutsideArray,
+
+    /// The key of a dict entry must be a basic type rather than a container type.
+    DictEntryKeyNotBasic,
+
+    /// Dict entries must have exactly two fields, a key and a value.
+    DictEntryFieldCount,
 
-// TODO impl from str for Signature see "Valid Signatures"
+    /// Containers are nested deeper than allowed.
+    Nesting(NestingError),
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureError::ExceedsMaxLength => write!(
+                f,
+                "Signature exceeds maximum length of {}",
+                MAX_SIGNATURE_LENGTH
+            ),
+            SignatureError::InvalidTypeCode(c) => {
+                write!(f, "Invalid type code `{}` in signature", c.escape_default())
+            }
+            SignatureError::EndsWithinCompleteType => {
+                write!(f, "Signature ends within a complete type")
+            }
+            SignatureError::UnexpectedClose(c) => write!(f, "Unexpected `{}` in signature", c),
+            SignatureError::EmptyStruct => write!(f, "Structs must have at least one field"),
+            SignatureError::DictEntryOutsideArray => {
+                write!(f, "Dict entries must be array elements")
+            }
+            SignatureError::DictEntryKeyNotBasic => {
+                write!(f, "Dict entry keys must be basic types")
+            }
+            SignatureError::DictEntryFieldCount => {
+                write!(f, "Dict entries must have exactly two fields")
+            }
+            SignatureError::Nesting(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+impl From<NestingError> for SignatureError {
+    fn from(err: NestingError) -> SignatureError {
+        SignatureError::Nesting(err)
+    }
+}
+
+impl From<SignatureError> for io::Error {
+    fn from(err: SignatureError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Whether `type_code` is one of the basic types, which alone may be the key of a dict entry.
+fn is_basic_type_code(type_code: u8) -> bool {
+    matches!(
+        type_code,
+        b'y' | b'b' | b'n' | b'q' | b'i' | b'u' | b'x' | b't' | b'd' | b'h' | b's' | b'o' | b'g'
+    )
+}
+
+/// Validates the single complete type at the start of `types`, returning the remaining types.
+/// The recursion is bounded by `depth`, so hostile signatures can not exhaust the stack.
+fn parse_complete_type(
+    types: &[u8],
+    depth: ContainerDepth,
+    array_element: bool,
+) -> Result<&[u8], SignatureError> {
+    let (type_code, rest) = types
+        .split_first()
+        .ok_or(SignatureError::EndsWithinCompleteType)?;
+    match type_code {
+        b'v' => Ok(rest),
+        b'a' => parse_complete_type(rest, depth.array()?, true),
+        b'(' => {
+            let field_depth = depth.structure()?;
+            if rest.first() == Some(&b')') {
+                return Err(SignatureError::EmptyStruct);
+            }
+            let mut rest_struct = rest;
+            loop {
+                match rest_struct.split_first() {
+                    Some((b')', rest)) => return Ok(rest),
+                    _ => rest_struct = parse_complete_type(rest_struct, field_depth, false)?,
+                }
+            }
+        }
+        b'{' => {
+            if !array_element {
+                return Err(SignatureError::DictEntryOutsideArray);
+            }
+            let field_depth = depth.structure()?;
+            let rest_key = match rest.split_first() {
+                Some((key, rest_key)) if is_basic_type_code(*key) => rest_key,
+                Some((b'}', _)) => return Err(SignatureError::DictEntryFieldCount),
+                Some(_) => return Err(SignatureError::DictEntryKeyNotBasic),
+                None => return Err(SignatureError::EndsWithinCompleteType),
+            };
+            if rest_key.first() == Some(&b'}') {
+                return Err(SignatureError::DictEntryFieldCount);
+            }
+            let rest_value = parse_complete_type(rest_key, field_depth, false)?;
+            match rest_value.split_first() {
+                Some((b'}', rest_entry)) => Ok(rest_entry),
+                Some(_) => Err(SignatureError::DictEntryFieldCount),
+                None => Err(SignatureError::EndsWithinCompleteType),
+            }
+        }
+        b')' | b'}' => Err(SignatureError::UnexpectedClose(char::from(*type_code))),
+        x if is_basic_type_code(*x) => Ok(rest),
+        x => Err(SignatureError::InvalidTypeCode(char::from(*x))),
+    }
+}
 
 impl DbusWrite for Signature {
-    fn write<T1, T2>(
-        &self,
-        writer: &mut DbusWriter<T1>,
-        bytes_written: u64,
-    ) -> Result<u64, io::Error>
-    where
-        T1: io::Write,
-        T2: ByteOrder,
-    {
-        writer.write_string::<T2>(&self.0, bytes_written)
+    fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64, io::Error> {
+        writer.write_signature(self.clone())
     }
 }
 
 /// based on "Basic type" - Table
-impl ToTypeCode for Signature {
-    fn to_type_code(&self) -> TypeCode {
-        "g".to_string()
-    }
+impl DbusType for Signature {
+    const SIGNATURE: &'static str = "g";
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct UnixFd(pub u32);
 
 /// based on "Basic type" - Table
-impl ToTypeCode for UnixFd {
-    fn to_type_code(&self) -> TypeCode {
-        "h".to_string()
-    }
+impl DbusType for UnixFd {
+    const SIGNATURE: &'static str = "h";
 }
 
 /// based on "Basic type" - Table
-impl ToTypeCode for u8 {
-    fn to_type_code(&self) -> TypeCode {
-        "y".to_string()
-    }
+impl DbusType for u8 {
+    const SIGNATURE: &'static str = "y";
 }
 
 /// based on "Basic type" - Table
-impl ToTypeCode for bool {
-    fn to_type_code(&self) -> TypeCode {
-        "b".to_string()
-    }
+impl DbusType for bool {
+    const SIGNATURE: &'static str = "b";
 }
 
 /// based on "Basic type" - Table
-impl ToTypeCode for i16 {
-    fn to_type_code(&self) -> TypeCode {
-        "n".to_string()
-    }
+impl DbusType for i16 {
+    const SIGNATURE: &'static str = "n";
 }
 
 /// based on "Basic type" - Table
-impl ToTypeCode for u16 {
-    fn to_type_code(&self) -> TypeCode {
-        "q".to_string()
-    }
+impl DbusType for u16 {
+    const SIGNATURE: &'static str = "q";
 }
 
 /// based on "Basic type" - Table
-impl ToTypeCode for i32 {
-    fn to_type_code(&self) -> TypeCode {
-        "i".to_string()
-    }
+impl DbusType for i32 {
+    const SIGNATURE: &'static str = "i";
 }
 
 /// based on "Basic type" - Table
-impl ToTypeCode for u32 {
-    fn to_type_code(&self) -> TypeCode {
-        "u".to_string()
-    }
+impl DbusType for u32 {
+    const SIGNATURE: &'static str = "u";
 }
 
 /// based on "Basic type" - Table
-impl ToTypeCode for i64 {
-    fn to_type_code(&self) -> TypeCode {
-        "x".to_string()
-    }
+impl DbusType for i64 {
+    const SIGNATURE: &'static str = "x";
 }
 
 /// based on "Basic type" - Table
-impl ToTypeCode for u64 {
-    fn to_type_code(&self) -> TypeCode {
-        "t".to_string()
-    }
+impl DbusType for u64 {
+    const SIGNATURE: &'static str = "t";
 }
 
 /// based on "Basic type" - Table
-impl ToTypeCode for f64 {
-    fn to_type_code(&self) -> TypeCode {
-        "d".to_string()
-    }
+impl DbusType for f64 {
+    const SIGNATURE: &'static str = "d";
 }
 
 /// based on "Basic type" - Table
-impl ToTypeCode for String {
-    fn to_type_code(&self) -> TypeCode {
-        "s".to_string()
-    }
+impl DbusType for String {
+    const SIGNATURE: &'static str = "s";
 }
 
 /// based on "Basic type" - Table
-impl ToTypeCode for &str {
-    fn to_type_code(&self) -> TypeCode {
-        "s".to_string()
-    }
+impl DbusType for &str {
+    const SIGNATURE: &'static str = "s";
 }
 
 /// based on "Basic type" - Table
 /// ARRAY has ASCII character 'a' as type code.
 /// The array type code must be followed by a single complete type.
 /// The single complete type following the array is the type of each array element.
-impl<T: ToTypeCode> ToTypeCode for Vec<T> {
-    fn to_type_code(&self) -> TypeCode {
-        let mut type_code = String::new();
-        type_code.push_str("a");
-        for x in self.iter() {
-            type_code.push_str(&x.to_type_code());
-        }
-        type_code
-    }
+impl<T: DbusType> DbusType for Vec<T> {
+    const SIGNATURE: &'static str = ConstSignature::new("a").append(T::SIGNATURE).as_str();
 }
 
 impl DbusWrite for Serial {
-    fn write<T1, T2>(
-        &self,
-        writer: &mut DbusWriter<T1>,
-        bytes_written: u64,
-    ) -> Result<u64, io::Error>
-    where
-        T1: io::Write,
-        T2: ByteOrder,
-    {
-        writer.write_u32::<T2>(self.0, bytes_written)
+    fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64, io::Error> {
+        writer.write_u32(self.get())
     }
 }
 
-/// /// A DICT_ENTRY works exactly like a struct, but rather than parentheses
+impl DbusType for Serial {
+    const SIGNATURE: &'static str = "u";
+}
+
+/// A DICT_ENTRY works exactly like a struct, but rather than parentheses
 /// it uses curly braces, and it has more restrictions.
-impl<K, V, S> ToTypeCode for HashMap<K, V, S>
+/// A dict is an array of dict entries, e.g. `a{sv}`.
+const fn dict_signature(key: &str, value: &str) -> ConstSignature {
+    ConstSignature::new("a{")
+        .append(key)
+        .append(value)
+        .append("}")
+}
+
+/// Each dict entry starts on an 8-byte boundary, the array length excludes
+/// the padding before the first entry.
+fn write_dict<'a, K, V, I>(
+    writer: &mut DbusWriter<dyn io::Write + '_>,
+    entries: I,
+) -> Result<u64, io::Error>
 where
-    K: BasicType + ToTypeCode + Eq + Hash,
-    V: ToTypeCode,
+    K: DbusWrite + 'a,
+    V: DbusWrite + 'a,
+    I: Iterator<Item = (&'a K, &'a V)>,
+{
+    writer.write_array_with(8, |writer| {
+        let mut entries_bytes_written = 0;
+        for (k, v) in entries {
+            entries_bytes_written =
+                u64::saturating_add(entries_bytes_written, writer.write_dict_entry(k, v)?);
+        }
+        Ok(entries_bytes_written)
+    })
+}
+
+impl<K, V, S> DbusType for HashMap<K, V, S>
+where
+    K: BasicType + DbusType + Eq + Hash,
+    V: DbusType,
     S: BuildHasher,
 {
-    fn to_type_code(&self) -> TypeCode {
-        let mut type_code = String::new();
-        type_code.push_str("{");
+    const SIGNATURE: &'static str = dict_signature(K::SIGNATURE, V::SIGNATURE).as_str();
+}
+
+impl<K, V, S> DbusWrite for HashMap<K, V, S>
+where
+    K: BasicType + DbusWrite + Eq + Hash,
+    V: DbusWrite,
+    S: BuildHasher,
+{
+    fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64, io::Error> {
+        write_dict(writer, self.iter())
+    }
+}
+
+impl<K, V, S> DbusRead for HashMap<K, V, S>
+where
+    K: BasicType + DbusRead + Eq + Hash,
+    V: DbusRead,
+    S: BuildHasher + Default,
+{
+    fn read(reader: &mut DbusReader<dyn io::Read + '_>) -> Result<HashMap<K, V, S>, io::Error> {
+        let mut map = HashMap::with_hasher(S::default());
+        reader.read_array_with(8, |reader| {
+            let (k, v) = reader.read_dict_entry::<K, V>()?;
+            map.insert(k, v);
+            Ok(())
+        })?;
+        Ok(map)
+    }
+}
+
+impl<K, V> DbusType for BTreeMap<K, V>
+where
+    K: BasicType + DbusType + Ord,
+    V: DbusType,
+{
+    const SIGNATURE: &'static str = dict_signature(K::SIGNATURE, V::SIGNATURE).as_str();
+}
+
+impl<K, V> DbusWrite for BTreeMap<K, V>
+where
+    K: BasicType + DbusWrite + Ord,
+    V: DbusWrite,
+{
+    fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64, io::Error> {
+        write_dict(writer, self.iter())
+    }
+}
+
+impl<K, V> DbusRead for BTreeMap<K, V>
+where
+    K: BasicType + DbusRead + Ord,
+    V: DbusRead,
+{
+    fn read(reader: &mut DbusReader<dyn io::Read + '_>) -> Result<BTreeMap<K, V>, io::Error> {
+        let mut map = BTreeMap::new();
+        reader.read_array_with(8, |reader| {
+            let (k, v) = reader.read_dict_entry::<K, V>()?;
+            map.insert(k, v);
+            Ok(())
+        })?;
+        Ok(map)
+    }
+}
+
+/// STRUCT has a type code, ASCII character 'r', but this type code does not appear in signatures.
+/// Instead, ASCII characters '(' and ')' are used to mark the beginning and end of the struct.
+/// A struct must start on an 8-byte boundary regardless of the type of the struct fields.
+macro_rules! impl_tuple {
+    ($($t:ident),+) => {
+        impl<$($t: DbusType),+> DbusType for ($($t,)+) {
+            const SIGNATURE: &'static str = ConstSignature::new("(")
+                $(.append($t::SIGNATURE))+
+                .append(")")
+                .as_str();
+        }
+
+        impl<$($t: DbusWrite),+> DbusWrite for ($($t,)+) {
+            #[allow(non_snake_case)]
+            fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64, io::Error> {
+                let ($($t,)+) = self;
+                writer.nested(|writer| {
+                    let mut struct_bytes_written = u64::from(writer.write_padding(8)?);
+                    $(struct_bytes_written =
+                        struct_bytes_written.saturating_add($t.write(writer)?);)+
+                    Ok(struct_bytes_written)
+                })
+            }
+        }
 
-        if let Some((key, value)) = self.iter().next() {
-            type_code.push_str(&key.to_type_code());
-            type_code.push_str(&value.to_type_code());
+        impl<$($t: DbusRead),+> DbusRead for ($($t,)+) {
+            fn read(reader: &mut DbusReader<dyn io::Read + '_>) -> Result<($($t,)+), io::Error> {
+                reader.read_padding(8)?;
+                Ok(($($t::read(reader)?,)+))
+            }
         }
+    };
+}
+
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+impl_tuple!(A, B, C, D, E);
+impl_tuple!(A, B, C, D, E, F);
+impl_tuple!(A, B, C, D, E, F, G);
+impl_tuple!(A, B, C, D, E, F, G, H);
+impl_tuple!(A, B, C, D, E, F, G, H, I);
+impl_tuple!(A, B, C, D, E, F, G, H, I, J);
+impl_tuple!(A, B, C, D, E, F, G, H, I, J, K);
+impl_tuple!(A, B, C, D, E, F, G, H, I, J, K, L);
+
+/// Returned when a `Value` can not be converted into the requested Rust type.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ValueError {
+    /// The value holds a different D-Bus type than the requested one.
+    UnexpectedType,
 
-        type_code.push_str("}");
-        type_code
+    /// The struct holds a different number of fields than the requested tuple.
+    UnexpectedStructLength { expected: usize, found: usize },
+
+    /// The element type of an empty array can not be derived from its elements.
+    UnknownElementType,
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValueError::UnexpectedType => write!(f, "Unexpected value type"),
+            ValueError::UnexpectedStructLength { expected, found } => write!(
+                f,
+                "Expected struct with {} fields, found {}",
+                expected, found
+            ),
+            ValueError::UnknownElementType => write!(f, "Unknown element type of empty array"),
+        }
     }
 }
+
+impl std::error::Error for ValueError {}
+
+/// Returns the types following the first complete type in `types`.
+pub(crate) fn skip_complete_type(types: &[u8]) -> io::Result<&[u8]> {
+    let mut open = 0usize;
+    let mut rest = types;
+    while let Some((type_code, rest_types)) = rest.split_first() {
+        rest = rest_types;
+        match type_code {
+            b'a' => continue,
+            b'(' | b'{' => open = open.saturating_add(1),
+            b')' | b'}' => {
+                open = open.checked_sub(1).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "Unbalanced signature")
+                })?
+            }
+            _ => (),
+        }
+        if open == 0 {
+            return Ok(rest);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Signature ends within a complete type",
+    ))
+}
+
+/// A dynamically typed D-Bus value, used wherever the type of a value is
+/// only known at runtime, e.g. when inspecting a received message.
+#[derive(Clone, PartialEq)]
+pub enum Value {
+    Byte(u8),
+    Boolean(bool),
+    Int16(i16),
+    Uint16(u16),
+    Int32(i32),
+    Uint32(u32),
+    Int64(i64),
+    Uint64(u64),
+    Double(f64),
+    String(String),
+    ObjectPath(ObjectPath),
+    Signature(Signature),
+    UnixFd(UnixFd),
+    /// ARRAY, a dict is an array of `Value::DictEntry` elements.
+    Array(Vec<Value>),
+    /// ARRAY of BYTE, which is unmarshaled as is instead of as `Value::Byte` elements
+    /// since byte arrays may be as large as the message.
+    ByteArray(Vec<u8>),
+    /// STRUCT, a sequence of one or more fields.
+    Struct(Vec<Value>),
+    /// VARIANT, a single value of any complete type along with its signature.
+    Variant(Box<Variant>),
+    /// DICT_ENTRY, only valid as element of an array.
+    DictEntry(Box<Value>, Box<Value>),
+}
+
+impl Value {
+    /// The signature of the single complete type of this value.
+    /// The element type of an array is derived from its elements, which must all be of the same type.
+    pub fn signature(&self) -> Result<Signature, ValueError> {
+        let signature = match self {
+            Value::Byte(_) => "y".to_string(),
+            Value::Boolean(_) => "b".to_string(),
+            Value::Int16(_) => "n".to_string(),
+            Value::Uint16(_) => "q".to_string(),
+            Value::Int32(_) => "i".to_string(),
+            Value::Uint32(_) => "u".to_string(),
+            Value::Int64(_) => "x".to_string(),
+            Value::Uint64(_) => "t".to_string(),
+            Value::Double(_) => "d".to_string(),
+            Value::String(_) => "s".to_string(),
+            Value::ObjectPath(_) => "o".to_string(),
+            Value::Signature(_) => "g".to_string(),
+            Value::UnixFd(_) => "h".to_string(),
+            Value::Array(elements) => {
+                let element_signature = elements
+                    .first()
+                    .ok_or(ValueError::UnknownElementType)?
+                    .signature()?;
+                for element in elements.iter().skip(1) {
+                    if element.signature()? != element_signature {
+                        return Err(ValueError::UnexpectedType);
+                    }
+                }
+                format!("a{}", element_signature.0)
+            }
+            Value::Struct(fields) => {
+                let mut signature = "(".to_string();
+                for field in fields {
+                    signature.push_str(&field.signature()?.0);
+                }
+                signature.push(')');
+                signature
+            }
+            Value::ByteArray(_) => "ay".to_string(),
+            Value::Variant(_) => "v".to_string(),
+            Value::DictEntry(k, v) => format!("{{{}{}}}", k.signature()?.0, v.signature()?.0),
+        };
+        Ok(Signature(signature.into()))
+    }
+
+    /// Unmarshals a value of the single complete type `signature`.
+    pub fn read(
+        reader: &mut DbusReader<dyn io::Read + '_>,
+        signature: &Signature,
+    ) -> io::Result<Value> {
+        let (value, rest) =
+            Value::read_complete_type(reader, signature.0.as_bytes(), ContainerDepth::default())?;
+        if !rest.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Signature must be a single complete type",
+            ));
+        }
+        Ok(value)
+    }
+
+    /// Unmarshals a value for each complete type in `signature`, e.g. the arguments of a message body.
+    pub fn read_all(
+        reader: &mut DbusReader<dyn io::Read + '_>,
+        signature: &Signature,
+    ) -> io::Result<Vec<Value>> {
+        let mut values = Vec::new();
+        let mut types = signature.0.as_bytes();
+        while !types.is_empty() {
+            let (value, rest) =
+                Value::read_complete_type(reader, types, ContainerDepth::default())?;
+            values.push(value);
+            types = rest;
+        }
+        Ok(values)
+    }
+
+    /// Unmarshals a value of the first complete type in `types`, returning it along with the remaining types.
+    fn read_complete_type<'a>(
+        reader: &mut DbusReader<dyn io::Read + '_>,
+        types: &'a [u8],
+        depth: ContainerDepth,
+    ) -> io::Result<(Value, &'a [u8])> {
+        let (type_code, rest) = match types.split_first() {
+            Some((type_code, rest)) => (*type_code, rest),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Signature ends within a complete type",
+                ))
+            }
+        };
+
+        let value = match type_code {
+            b'y' => Value::Byte(reader.read_u8()?),
+            b'b' => Value::Boolean(reader.read_boolean()?),
+            b'n' => Value::Int16(reader.read_i16()?),
+            b'q' => Value::Uint16(reader.read_u16()?),
+            b'i' => Value::Int32(reader.read_i32()?),
+            b'u' => Value::Uint32(reader.read_u32()?),
+            b'x' => Value::Int64(reader.read_i64()?),
+            b't' => Value::Uint64(reader.read_u64()?),
+            b'd' => Value::Double(reader.read_f64()?),
+            b'h' => Value::UnixFd(reader.read_unix_fd()?),
+            b's' => Value::String(reader.read_string()?),
+            b'o' => Value::ObjectPath(reader.read_object_path()?),
+            b'g' => Value::Signature(reader.read_signature()?),
+            b'v' => {
+                let signature = reader.read_signature()?;
+                let (value, rest_variant) =
+                    Value::read_complete_type(reader, signature.0.as_bytes(), depth.variant()?)?;
+                if !rest_variant.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Variant signature must be a single complete type",
+                    ));
+                }
+                Value::from(Variant::from_parts(signature, value))
+            }
+            b'a' => {
+                let len = reader.read_u32()?;
+                if len > MAX_ARRAY_LENGTH {
+                    let str_err = format!("Array length `{}` exceeds maximum", len);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+                }
+                let element_type_code = rest.first().map_or(' ', |c| char::from(*c));
+                reader.read_padding(Signature::alignment_of(element_type_code))?;
+
+                let rest_array = skip_complete_type(rest)?;
+                let element_depth = depth.array()?;
+                if element_type_code == 'y' {
+                    let bytes = reader.read_bytes(u64::from(len))?;
+                    return Ok((Value::ByteArray(bytes), rest_array));
+                }
+                let end = reader.position().saturating_add(u64::from(len));
+                let mut elements = Vec::new();
+                while reader.position() < end {
+                    let (element, _) = Value::read_complete_type(reader, rest, element_depth)?;
+                    elements.push(element);
+                }
+                if reader.position() != end {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Array elements exceed the array length",
+                    ));
+                }
+                return Ok((Value::Array(elements), rest_array));
+            }
+            b'(' => {
+                reader.read_padding(8)?;
+                let field_depth = depth.structure()?;
+                let mut fields = Vec::new();
+                let mut rest_struct = rest;
+                let rest_struct = loop {
+                    match rest_struct.split_first() {
+                        Some((b')', rest_struct)) => break rest_struct,
+                        _ => {
+                            let (field, rest_field) =
+                                Value::read_complete_type(reader, rest_struct, field_depth)?;
+                            fields.push(field);
+                            rest_struct = rest_field;
+                        }
+                    }
+                };
+                if fields.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Structs must have at least one field",
+                    ));
+                }
+                return Ok((Value::Struct(fields), rest_struct));
+            }
+            b'{' => {
+                reader.read_padding(8)?;
+                let field_depth = depth.structure()?;
+                let (k, rest_key) = Value::read_complete_type(reader, rest, field_depth)?;
+                let (v, rest_value) = Value::read_complete_type(reader, rest_key, field_depth)?;
+                return match rest_value.split_first() {
+                    Some((b'}', rest_entry)) => {
+                        Ok((Value::DictEntry(Box::new(k), Box::new(v)), rest_entry))
+                    }
+                    _ => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Dict entries must have exactly two fields",
+                    )),
+                };
+            }
+            x => {
+                let str_err = format!("Invalid type code `{}` in signature", char::from(x));
+                return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+            }
+        };
+        Ok((value, rest))
+    }
+
+    /// Marshals this value as the single complete type `signature`. Unlike `write`, this
+    /// does not derive the signature from the value, so empty arrays can be marshaled.
+    pub fn write_with_signature(
+        &self,
+        writer: &mut DbusWriter<dyn io::Write + '_>,
+        signature: &Signature,
+    ) -> io::Result<u64> {
+        let (bytes_written, rest) = self.write_complete_type(writer, signature.0.as_bytes())?;
+        if !rest.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Signature must be a single complete type",
+            ));
+        }
+        Ok(bytes_written)
+    }
+
+    /// Marshals this value as the first complete type in `types`, returning the number of
+    /// bytes written along with the remaining types.
+    fn write_complete_type<'a>(
+        &self,
+        writer: &mut DbusWriter<dyn io::Write + '_>,
+        types: &'a [u8],
+    ) -> io::Result<(u64, &'a [u8])> {
+        let mismatch = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Value does not match its signature",
+            )
+        };
+        let (type_code, rest) = match types.split_first() {
+            Some((type_code, rest)) => (*type_code, rest),
+            None => return Err(mismatch()),
+        };
+
+        match self {
+            Value::Array(elements) if type_code == b'a' => {
+                let element_type_code = rest.first().map_or(' ', |c| char::from(*c));
+                let bytes_written = writer.write_array_with(
+                    Signature::alignment_of(element_type_code),
+                    |writer| {
+                        let mut elements_bytes_written = 0u64;
+                        for element in elements {
+                            let (element_bytes_written, _) =
+                                element.write_complete_type(writer, rest)?;
+                            elements_bytes_written =
+                                elements_bytes_written.saturating_add(element_bytes_written);
+                        }
+                        Ok(elements_bytes_written)
+                    },
+                )?;
+                Ok((bytes_written, skip_complete_type(rest)?))
+            }
+            Value::ByteArray(bytes) if type_code == b'a' && rest.first() == Some(&b'y') => {
+                let bytes_written =
+                    writer.write_array_with(1, |writer| writer.write_bytes(bytes))?;
+                Ok((bytes_written, skip_complete_type(rest)?))
+            }
+            Value::Struct(fields) if type_code == b'(' => writer.nested(|writer| {
+                let mut struct_bytes_written = u64::from(writer.write_padding(8)?);
+                let mut rest_struct = rest;
+                for field in fields {
+                    let (field_bytes_written, rest_field) =
+                        field.write_complete_type(writer, rest_struct)?;
+                    struct_bytes_written = struct_bytes_written.saturating_add(field_bytes_written);
+                    rest_struct = rest_field;
+                }
+                match rest_struct.split_first() {
+                    Some((b')', rest_struct)) => Ok((struct_bytes_written, rest_struct)),
+                    _ => Err(mismatch()),
+                }
+            }),
+            Value::DictEntry(k, v) if type_code == b'{' => writer.nested(|writer| {
+                let entry_bytes_written = u64::from(writer.write_padding(8)?);
+                let (key_bytes_written, rest_key) = k.write_complete_type(writer, rest)?;
+                let (value_bytes_written, rest_value) = v.write_complete_type(writer, rest_key)?;
+                let entry_bytes_written = entry_bytes_written
+                    .saturating_add(key_bytes_written)
+                    .saturating_add(value_bytes_written);
+                match rest_value.split_first() {
+                    Some((b'}', rest_entry)) => Ok((entry_bytes_written, rest_entry)),
+                    _ => Err(mismatch()),
+                }
+            }),
+            Value::Array(_) | Value::ByteArray(_) | Value::Struct(_) | Value::DictEntry(_, _) => {
+                Err(mismatch())
+            }
+            _ => {
+                let signature = self
+                    .signature()
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+                if signature.0.as_bytes() != [type_code] {
+                    return Err(mismatch());
+                }
+                Ok((self.write(writer)?, rest))
+            }
+        }
+    }
+
+    /// Wraps `v` in a VARIANT, failing if its signature can not be derived, see `Variant::new`.
+    pub fn variant_of<T: Into<Value>>(v: T) -> Result<Value, ValueError> {
+        Ok(Value::from(Variant::new(v)?))
+    }
+
+    /// Wraps this value in a VARIANT, failing if its signature can not be derived.
+    pub fn into_variant(self) -> Result<Value, ValueError> {
+        Value::variant_of(self)
+    }
+}
+
+impl DbusWrite for Value {
+    fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64, io::Error> {
+        match self {
+            Value::Byte(b) => writer.write_u8(*b),
+            Value::Boolean(b) => writer.write_boolean(*b),
+            Value::Int16(i) => writer.write_i16(*i),
+            Value::Uint16(u) => writer.write_u16(*u),
+            Value::Int32(i) => writer.write_i32(*i),
+            Value::Uint32(u) => writer.write_u32(*u),
+            Value::Int64(i) => writer.write_i64(*i),
+            Value::Uint64(u) => writer.write_u64(*u),
+            Value::String(s) => writer.write_string(s),
+            Value::ObjectPath(object_path) => object_path.write(writer),
+            Value::Signature(signature) => signature.write(writer),
+            Value::UnixFd(fd) => writer.write_u32(fd.0),
+            Value::Double(d) => writer.write_f64(*d),
+            Value::Array(elements) => {
+                let signature = self
+                    .signature()
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+                let element_signature = Signature(signature.0[1..].to_string().into());
+                writer.write_array(elements, &element_signature)
+            }
+            Value::ByteArray(bytes) => {
+                writer.write_array_with(1, |writer| writer.write_bytes(bytes))
+            }
+            Value::Struct(fields) => writer.nested(|writer| {
+                let mut struct_bytes_written = u64::from(writer.write_padding(8)?);
+                for field in fields {
+                    struct_bytes_written =
+                        struct_bytes_written.saturating_add(field.write(writer)?);
+                }
+                Ok(struct_bytes_written)
+            }),
+            Value::DictEntry(k, v) => writer.nested(|writer| {
+                let entry_bytes_written = u64::from(writer.write_padding(8)?);
+                Ok(entry_bytes_written
+                    .saturating_add(k.write(writer)?)
+                    .saturating_add(v.write(writer)?))
+            }),
+            Value::Variant(v) => v.write(writer),
+        }
+    }
+}
+
+macro_rules! impl_value_conversion {
+    ($t:ty, $variant:ident) => {
+        impl From<$t> for Value {
+            fn from(v: $t) -> Value {
+                Value::$variant(v)
+            }
+        }
+
+        impl TryFrom<Value> for $t {
+            type Error = ValueError;
+
+            fn try_from(v: Value) -> Result<$t, ValueError> {
+                match v {
+                    Value::$variant(v) => Ok(v),
+                    _ => Err(ValueError::UnexpectedType),
+                }
+            }
+        }
+    };
+}
+
+impl_value_conversion!(u8, Byte);
+impl_value_conversion!(bool, Boolean);
+impl_value_conversion!(i16, Int16);
+impl_value_conversion!(u16, Uint16);
+impl_value_conversion!(i32, Int32);
+impl_value_conversion!(u32, Uint32);
+impl_value_conversion!(i64, Int64);
+impl_value_conversion!(u64, Uint64);
+impl_value_conversion!(f64, Double);
+impl_value_conversion!(String, String);
+impl_value_conversion!(ObjectPath, ObjectPath);
+impl_value_conversion!(Signature, Signature);
+impl_value_conversion!(UnixFd, UnixFd);
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(vec: Vec<T>) -> Value {
+        Value::Array(vec.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T> TryFrom<Value> for Vec<T>
+where
+    T: TryFrom<Value, Error = ValueError>,
+{
+    type Error = ValueError;
+
+    fn try_from(v: Value) -> Result<Vec<T>, ValueError> {
+        match v {
+            Value::Array(elements) => elements.into_iter().map(T::try_from).collect(),
+            Value::ByteArray(bytes) => bytes
+                .into_iter()
+                .map(Value::Byte)
+                .map(T::try_from)
+                .collect(),
+            _ => Err(ValueError::UnexpectedType),
+        }
+    }
+}
+
+impl<K, V, S> From<HashMap<K, V, S>> for Value
+where
+    K: BasicType + Into<Value>,
+    V: Into<Value>,
+    S: BuildHasher,
+{
+    fn from(map: HashMap<K, V, S>) -> Value {
+        Value::Array(
+            map.into_iter()
+                .map(|(k, v)| Value::DictEntry(Box::new(k.into()), Box::new(v.into())))
+                .collect(),
+        )
+    }
+}
+
+impl<K, V, S> TryFrom<Value> for HashMap<K, V, S>
+where
+    K: BasicType + TryFrom<Value, Error = ValueError> + Eq + Hash,
+    V: TryFrom<Value, Error = ValueError>,
+    S: BuildHasher + Default,
+{
+    type Error = ValueError;
+
+    fn try_from(v: Value) -> Result<HashMap<K, V, S>, ValueError> {
+        let entries = match v {
+            Value::Array(entries) => entries,
+            _ => return Err(ValueError::UnexpectedType),
+        };
+
+        let mut map = HashMap::with_capacity_and_hasher(entries.len(), S::default());
+        for entry in entries {
+            match entry {
+                Value::DictEntry(k, v) => {
+                    map.insert(K::try_from(*k)?, V::try_from(*v)?);
+                }
+                _ => return Err(ValueError::UnexpectedType),
+            }
+        }
+        Ok(map)
+    }
+}
+
+macro_rules! impl_value_conversion_tuple {
+    ($len:expr, $($t:ident),+) => {
+        impl<$($t: Into<Value>),+> From<($($t,)+)> for Value {
+            #[allow(non_snake_case)]
+            fn from(($($t,)+): ($($t,)+)) -> Value {
+                Value::Struct(vec![$($t.into()),+])
+            }
+        }
+
+        impl<$($t: TryFrom<Value, Error = ValueError>),+> TryFrom<Value> for ($($t,)+) {
+            type Error = ValueError;
+
+            fn try_from(v: Value) -> Result<($($t,)+), ValueError> {
+                let fields = match v {
+                    Value::Struct(fields) => fields,
+                    _ => return Err(ValueError::UnexpectedType),
+                };
+
+                if fields.len() != $len {
+                    return Err(ValueError::UnexpectedStructLength {
+                        expected: $len,
+                        found: fields.len(),
+                    });
+                }
+
+                let mut fields = fields.into_iter();
+                Ok(($($t::try_from(fields.next().ok_or(ValueError::UnexpectedType)?)?,)+))
+            }
+        }
+    };
+}
+
+impl_value_conversion_tuple!(1, A);
+impl_value_conversion_tuple!(2, A, B);
+impl_value_conversion_tuple!(3, A, B, C);
+impl_value_conversion_tuple!(4, A, B, C, D);
+impl_value_conversion_tuple!(5, A, B, C, D, E);
+impl_value_conversion_tuple!(6, A, B, C, D, E, F);
+impl_value_conversion_tuple!(7, A, B, C, D, E, F, G);
+impl_value_conversion_tuple!(8, A, B, C, D, E, F, G, H);
+impl_value_conversion_tuple!(9, A, B, C, D, E, F, G, H, I);
+impl_value_conversion_tuple!(10, A, B, C, D, E, F, G, H, I, J);
+impl_value_conversion_tuple!(11, A, B, C, D, E, F, G, H, I, J, K);
+impl_value_conversion_tuple!(12, A, B, C, D, E, F, G, H, I, J, K, L);
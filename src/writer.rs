@@ -1,28 +1,256 @@
-use crate::type_system::{ObjectPath, Signature};
-use byteorder::{ByteOrder, WriteBytesExt};
+use crate::endianness::Endianness;
+use crate::limits::{MAX_ARRAY_LENGTH, MAX_MESSAGE_SIZE, MAX_SIGNATURE_DEPTH};
+use crate::type_system::{DbusType, ObjectPath, Signature, StringError};
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
 use std::io;
+use std::io::{SeekFrom, Write};
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::type_system::Serial;
+    use std::convert::TryFrom;
+    use std::io::Cursor;
+
+    #[test]
+    fn write_array_from_iter() {
+        let mut writer = DbusWriter::new(Cursor::new(Vec::new()), Endianness::Little);
+        let elements = (1..=2).map(|s| Serial::try_from(s).unwrap());
+        let len = writer
+            .write_array_from_iter(elements, &Signature("u".into()))
+            .unwrap();
+
+        assert_eq!(12, len);
+        assert_eq!(
+            vec![8, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0],
+            writer.into_inner().into_inner()
+        );
+    }
+
+    /// Array nested `self.0` levels deep
+    struct Nested(usize);
+
+    impl DbusWrite for Nested {
+        fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64> {
+            if self.0 == 0 {
+                return writer.write_u8(0);
+            }
+            let element_signature = Signature(format!("{}y", "a".repeat(self.0 - 1)).into());
+            writer.write_array(&[Nested(self.0 - 1)], &element_signature)
+        }
+    }
+
+    #[test]
+    fn write_array() {
+        let mut buffer = Vec::new();
+        let mut writer = DbusWriter::new(&mut buffer, Endianness::Little);
+        let mut len = writer.write_u8(1).unwrap();
+        len += writer
+            .write_array(&[2u16, 3u16], &Signature("q".into()))
+            .unwrap();
+        len += writer
+            .write_array(&[(4u8,)], &Signature("(y)".into()))
+            .unwrap();
+        assert_eq!(writer.position(), len);
+        assert_eq!(buffer.len() as u64, len);
+        assert_eq!(
+            vec![1, 0, 0, 0, 4, 0, 0, 0, 2, 0, 3, 0, 1, 0, 0, 0, 4],
+            buffer
+        );
+    }
+
+    #[test]
+    fn write_string() {
+        let mut buffer = Vec::new();
+        let mut writer = DbusWriter::new(&mut buffer, Endianness::Little);
+        let mut len = writer.write_u8(1).unwrap();
+        len += writer.write_string("ab").unwrap();
+        assert_eq!(buffer.len() as u64, len);
+        assert_eq!(vec![1, 0, 0, 0, 2, 0, 0, 0, b'a', b'b', 0], buffer);
+
+        let err = DbusWriter::new(io::sink(), Endianness::Little)
+            .write_string("a\0b")
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        assert_eq!(
+            Some(&StringError::InteriorNul),
+            err.get_ref().and_then(|e| e.downcast_ref())
+        );
+    }
+
+    #[test]
+    fn write_f64() {
+        let mut buffer = Vec::new();
+        let mut writer = DbusWriter::new(&mut buffer, Endianness::Big);
+        let mut len = writer.write_u8(1).unwrap();
+        len += 36.6f64.write(&mut writer).unwrap();
+        assert_eq!(16, len);
+        assert_eq!(
+            vec![1, 0, 0, 0, 0, 0, 0, 0, 0x40, 0x42, 0x4C, 0xCC, 0xCC, 0xCC, 0xCC, 0xCD],
+            buffer
+        );
+    }
+
+    #[test]
+    fn write_trait_objects() {
+        let values: Vec<Box<dyn DbusWrite>> =
+            vec![Box::new(1u8), Box::new(2u32), Box::new("a".to_string())];
+        let mut buffer = Vec::new();
+        let mut writer = DbusWriter::new(&mut buffer, Endianness::Big);
+        let mut len = 0;
+        for v in values.iter() {
+            len += v.write(&mut writer).unwrap();
+        }
+        assert_eq!(buffer.len() as u64, len);
+        assert_eq!(vec![1, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 1, b'a', 0], buffer);
+    }
+
+    #[test]
+    fn write_variant() {
+        let mut buffer = Vec::new();
+        let writer: &mut DbusWriter<dyn io::Write> =
+            &mut DbusWriter::new(&mut buffer, Endianness::Little);
+        writer.write_u8(7).unwrap();
+        let len = writer.write_variant(&(1u8, 2u64)).unwrap();
+
+        assert_eq!(buffer.len() as u64 - 1, len);
+        assert_eq!(
+            vec![
+                7, 4, b'(', b'y', b't', b')', 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0
+            ],
+            buffer
+        );
+    }
+
+    #[test]
+    fn write_array_exceeds_max_length() {
+        let elements = vec![0u8; MAX_ARRAY_LENGTH as usize + 1];
+        let mut writer = DbusWriter::new(io::sink(), Endianness::Little);
+        let err = writer
+            .write_array(&elements, &Signature("y".into()))
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    }
+
+    #[test]
+    fn write_array_nesting_depth() {
+        let mut buffer = Vec::new();
+        let mut writer = DbusWriter::new(&mut buffer, Endianness::Little);
+        assert!(Nested(MAX_SIGNATURE_DEPTH).write(&mut writer).is_ok());
+        assert!(Nested(MAX_SIGNATURE_DEPTH + 1).write(&mut writer).is_err());
+        assert_eq!(0, writer.container_depth);
+    }
+
+    #[test]
+    fn write_array_from_iter_element_padding() {
+        let mut writer = DbusWriter::new(Cursor::new(Vec::new()), Endianness::Little);
+        let elements = Vec::<Serial>::new();
+        let len = writer
+            .write_array_from_iter(elements, &Signature("(u)".into()))
+            .unwrap();
+
+        // Padding to the element alignment is not part of the array length
+        assert_eq!(8, len);
+        assert_eq!(vec![0; 8], writer.into_inner().into_inner());
+    }
+}
 
 type Result<T> = std::result::Result<T, std::io::Error>;
 
+/// Writes `$value` to `$writer` using the `WriteBytesExt` method `$write` in the given byte order.
+macro_rules! write_ordered {
+    ($endianness:expr, $writer:expr, $write:ident, $value:expr) => {
+        match $endianness {
+            Endianness::Little => $writer.$write::<LittleEndian>($value),
+            Endianness::Big => $writer.$write::<BigEndian>($value),
+        }
+    };
+}
+
+/// Marshals a value. The byte order is carried by the writer, so the trait is object safe
+/// and values of different types can be written through `dyn DbusWrite`.
 pub trait DbusWrite {
-    fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>, bytes_written: u64) -> Result<u64>
-    where
-        T1: io::Write,
-        T2: ByteOrder;
+    fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64>;
 }
 
-pub struct DbusWriter<T: io::Write> {
+/// Keeps track of the number of bytes written, which is required to align values.
+struct CountingWriter<T: io::Write + ?Sized> {
+    position: u64,
     writer: T,
 }
 
+impl<T: io::Write + ?Sized> io::Write for CountingWriter<T> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let len = self.writer.write(buf)?;
+        self.position += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+}
+
+pub struct DbusWriter<T: io::Write + ?Sized> {
+    endianness: Endianness,
+    /// Number of containers (arrays, structs, variants) currently being written.
+    container_depth: usize,
+    writer: CountingWriter<T>,
+}
+
 impl<T: io::Write> DbusWriter<T> {
-    pub fn new(writer: T) -> DbusWriter<T> {
-        DbusWriter { writer }
+    pub fn new(writer: T, endianness: Endianness) -> DbusWriter<T> {
+        DbusWriter {
+            endianness,
+            container_depth: 0,
+            writer: CountingWriter {
+                position: 0,
+                writer,
+            },
+        }
+    }
+
+    /// Unwraps this `DbusWriter`, returning the underlying writer.
+    pub fn into_inner(self) -> T {
+        self.writer.writer
+    }
+}
+
+impl<T: io::Write + ?Sized> DbusWriter<T> {
+    /// Byte order all multi-byte values are written in.
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
     }
 
-    /// Add padding to multiple of 8
-    pub fn write_padding(&mut self, bytes_written: u64, align_to: u64) -> Result<u8> {
-        let padding_length = (align_to - (bytes_written % align_to)) % align_to;
+    /// Number of bytes written so far, used to compute alignment padding.
+    pub fn position(&self) -> u64 {
+        self.writer.position
+    }
+
+    /// Runs `f` one container nesting level deeper, failing instead of producing
+    /// a message exceeding the maximum container depth other implementations must reject.
+    pub(crate) fn nested<R, F>(&mut self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Self) -> Result<R>,
+    {
+        if self.container_depth >= MAX_SIGNATURE_DEPTH {
+            let str_err = format!(
+                "Container nesting exceeds maximum depth of {}",
+                MAX_SIGNATURE_DEPTH
+            );
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, str_err));
+        }
+
+        self.container_depth += 1;
+        let result = f(self);
+        self.container_depth -= 1;
+        result
+    }
+
+    /// Add padding to a multiple of `align_to`
+    pub fn write_padding(&mut self, align_to: u64) -> Result<u8> {
+        let padding_length = (align_to - (self.position() % align_to)) % align_to;
         for _ in 0..padding_length {
             self.write_u8(0)?;
         }
@@ -42,102 +270,293 @@ impl<T: io::Write> DbusWriter<T> {
         Ok(1)
     }
 
+    /// Raw bytes without any alignment.
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<u64> {
+        self.writer.write_all(bytes)?;
+        Ok(bytes.len() as u64)
+    }
+
     /// As for UINT32, but only 0 and 1 are valid values.
-    pub fn write_boolean<T1: ByteOrder>(&mut self, b: bool, bytes_written: u64) -> Result<u64> {
-        self.write_u32::<T1>(b as u32, bytes_written)
+    pub fn write_boolean(&mut self, b: bool) -> Result<u64> {
+        self.write_u32(b as u32)
     }
 
     /// 16-bit signed integer in the message's byte order.
-    pub fn write_i16<T1: ByteOrder>(&mut self, i: i16, bytes_written: u64) -> Result<u64> {
-        self.write_padding(bytes_written, 2)?;
-        self.writer.write_i16::<T1>(i)?;
-        Ok(16 / 8)
+    pub fn write_i16(&mut self, i: i16) -> Result<u64> {
+        let padding = self.write_padding(2)?;
+        write_ordered!(self.endianness, self.writer, write_i16, i)?;
+        Ok(u64::from(padding) + 16 / 8)
     }
 
     /// 16-bit unsigned integer in the message's byte order.
-    pub fn write_u16<T1: ByteOrder>(&mut self, u: u16, bytes_written: u64) -> Result<u64> {
-        self.write_padding(bytes_written, 2)?;
-        self.writer.write_u16::<T1>(u)?;
-        Ok(16 / 8)
+    pub fn write_u16(&mut self, u: u16) -> Result<u64> {
+        let padding = self.write_padding(2)?;
+        write_ordered!(self.endianness, self.writer, write_u16, u)?;
+        Ok(u64::from(padding) + 16 / 8)
     }
 
     /// 32-bit signed integer in the message's byte order.
-    pub fn write_i32<T1: ByteOrder>(&mut self, i: i32, bytes_written: u64) -> Result<u64> {
-        self.write_padding(bytes_written, 4)?;
-        self.writer.write_i32::<T1>(i)?;
-        Ok(32 / 8)
+    pub fn write_i32(&mut self, i: i32) -> Result<u64> {
+        let padding = self.write_padding(4)?;
+        write_ordered!(self.endianness, self.writer, write_i32, i)?;
+        Ok(u64::from(padding) + 32 / 8)
     }
 
     /// 32-bit unsigned integer in the message's byte order.
-    pub fn write_u32<T1: ByteOrder>(&mut self, u: u32, bytes_written: u64) -> Result<u64> {
-        self.write_padding(bytes_written, 4)?;
-        self.writer.write_u32::<T1>(u)?;
-        Ok(32 / 8)
+    pub fn write_u32(&mut self, u: u32) -> Result<u64> {
+        let padding = self.write_padding(4)?;
+        write_ordered!(self.endianness, self.writer, write_u32, u)?;
+        Ok(u64::from(padding) + 32 / 8)
     }
 
     /// 64-bit signed integer in the message's byte order.
-    pub fn write_i64<T1: ByteOrder>(&mut self, i: i64, bytes_written: u64) -> Result<u64> {
-        self.write_padding(bytes_written, 8)?;
-        self.writer.write_i64::<T1>(i)?;
-        Ok(64 / 8)
+    pub fn write_i64(&mut self, i: i64) -> Result<u64> {
+        let padding = self.write_padding(8)?;
+        write_ordered!(self.endianness, self.writer, write_i64, i)?;
+        Ok(u64::from(padding) + 64 / 8)
     }
 
     /// 64-bit unsigned integer in the message's byte order.
-    pub fn write_u64<T1: ByteOrder>(&mut self, u: u64, bytes_written: u64) -> Result<u64> {
-        self.write_padding(bytes_written, 8)?;
-        self.writer.write_u64::<T1>(u)?;
-        Ok(64 / 8)
+    pub fn write_u64(&mut self, u: u64) -> Result<u64> {
+        let padding = self.write_padding(8)?;
+        write_ordered!(self.endianness, self.writer, write_u64, u)?;
+        Ok(u64::from(padding) + 64 / 8)
+    }
+
+    /// 64-bit IEEE 754 double in the message's byte order.
+    pub fn write_f64(&mut self, d: f64) -> Result<u64> {
+        let padding = self.write_padding(8)?;
+        write_ordered!(self.endianness, self.writer, write_f64, d)?;
+        Ok(u64::from(padding) + 64 / 8)
     }
 
     /// A UINT32 indicating the string's length in bytes excluding its terminating nul,
     /// followed by non-nul string data of the given length, followed by a terminating nul byte.
-    pub fn write_string<T1: ByteOrder>(&mut self, s: &str, bytes_written: u64) -> Result<u64> {
-        let mut bytes_written = 0;
-        bytes_written += self.write_u32::<T1>(s.len() as u32, bytes_written)?;
-
-        let s_bytes = s.as_bytes();
-        self.writer.write_all(s_bytes)?;
-        bytes_written += s_bytes.len() as u64;
+    pub fn write_string(&mut self, s: &str) -> Result<u64> {
+        if s.len() > MAX_MESSAGE_SIZE as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                StringError::ExceedsMaxSize,
+            ));
+        }
+        if s.as_bytes().contains(&b'\0') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                StringError::InteriorNul,
+            ));
+        }
 
-        bytes_written += self.write_u8(b'\n')?;
+        let mut string_bytes_written = self.write_u32(s.len() as u32)?;
+        string_bytes_written += self.write_bytes(s.as_bytes())?;
+        string_bytes_written += self.write_u8(b'\0')?;
 
-        Ok(bytes_written)
+        Ok(string_bytes_written)
     }
 
     /// Exactly the same as STRING except the content must be a valid object path (see above).
-    pub fn write_object_path<T1: ByteOrder>(
-        &mut self,
-        object_path: ObjectPath,
-        bytes_written: u64,
-    ) -> Result<u64> {
-        self.write_string::<T1>(&object_path.0, bytes_written)
+    pub fn write_object_path(&mut self, object_path: ObjectPath) -> Result<u64> {
+        self.write_string(&object_path.0)
     }
 
     /// The same as STRING except the length is a single byte (thus signatures
     /// have a maximum length of 255) and the content must be a valid signature (see above).
-    pub fn write_signature<T1: ByteOrder>(
-        &mut self,
-        signature: Signature,
-        bytes_written: u64,
-    ) -> Result<u64> {
-        self.write_string::<T1>(&signature.0, bytes_written)
+    pub fn write_signature(&mut self, signature: Signature) -> Result<u64> {
+        signature
+            .validate()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+        let mut signature_bytes_written = self.write_u8(signature.0.len() as u8)?;
+        signature_bytes_written += self.write_bytes(signature.0.as_bytes())?;
+        signature_bytes_written += self.write_u8(b'\0')?;
+        Ok(signature_bytes_written)
     }
 
     /// A UINT32 giving the length of the array data in bytes, followed by alignment
     /// padding to the alignment boundary of the array element type, followed by each array element.
-    pub fn write_array<T1: ByteOrder, T2: DbusWrite>(
+    /// Arrays have a maximum length defined to be 2 to the 26th power or 67108864 (64 MiB).
+    pub fn write_array<T1: DbusWrite>(
         &mut self,
-        a: &[T2],
-        bytes_written: u64,
+        a: &[T1],
+        element_signature: &Signature,
     ) -> Result<u64> {
-        let mut bytes_written = 0;
+        self.write_array_with(element_signature.alignment(), |writer| {
+            let mut elements_bytes_written = 0;
+            for x in a {
+                elements_bytes_written += x.write(writer)?;
+            }
+            Ok(elements_bytes_written)
+        })
+    }
+
+    /// Writes an array whose elements are marshaled by `f`. As the writer can not seek, the
+    /// elements are marshaled into a buffer first, so the byte length is known before they
+    /// are written. The buffer's position starts at the position of the first element,
+    /// so the elements are aligned as if they were written directly.
+    pub fn write_array_with<F>(&mut self, element_alignment: u64, f: F) -> Result<u64>
+    where
+        F: FnOnce(&mut DbusWriter<dyn io::Write + '_>) -> Result<u64>,
+    {
+        self.nested(|writer| {
+            let position = writer.position();
+            let length_end = position + 4 + (4 - position % 4) % 4;
+            let elements_start = length_end
+                + (element_alignment - length_end % element_alignment) % element_alignment;
+
+            let mut elements = DbusWriter {
+                endianness: writer.endianness,
+                container_depth: writer.container_depth,
+                writer: CountingWriter {
+                    position: elements_start,
+                    writer: Vec::new(),
+                },
+            };
+            f(&mut elements)?;
+
+            let array_len = elements.writer.writer.len() as u64;
+            if array_len > u64::from(MAX_ARRAY_LENGTH) {
+                let str_err = format!(
+                    "Array length `{}` exceeds maximum of {}",
+                    array_len, MAX_ARRAY_LENGTH
+                );
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, str_err));
+            }
+
+            let mut array_bytes_written = writer.write_u32(array_len as u32)?;
+            array_bytes_written += u64::from(writer.write_padding(element_alignment)?);
+            array_bytes_written += writer.write_bytes(&elements.writer.writer)?;
+            Ok(array_bytes_written)
+        })
+    }
+}
+
+impl DbusWriter<dyn io::Write + '_> {
+    /// A DICT_ENTRY works exactly like a struct holding a key and a value,
+    /// so it starts on an 8-byte boundary.
+    pub fn write_dict_entry<K, V>(&mut self, k: &K, v: &V) -> Result<u64>
+    where
+        K: DbusWrite,
+        V: DbusWrite,
+    {
+        self.nested(|writer| {
+            let mut entry_bytes_written = u64::from(writer.write_padding(8)?);
+            entry_bytes_written += k.write(writer)?;
+            entry_bytes_written += v.write(writer)?;
+            Ok(entry_bytes_written)
+        })
+    }
+
+    /// A VARIANT holding `value`: the signature of its single complete type,
+    /// followed by the value itself aligned to its own type.
+    pub fn write_variant<T>(&mut self, value: &T) -> Result<u64>
+    where
+        T: DbusType + DbusWrite,
+    {
+        self.write_variant_with(T::signature(), |writer| value.write(writer))
+    }
+
+    /// A VARIANT whose value is marshaled by `f` after the signature, for values whose
+    /// signature is known up front instead of derived, e.g. a header field or a received `Value`.
+    pub(crate) fn write_variant_with<F>(&mut self, signature: Signature, f: F) -> Result<u64>
+    where
+        F: FnOnce(&mut Self) -> Result<u64>,
+    {
+        self.nested(|writer| {
+            let mut variant_bytes_written = writer.write_signature(signature)?;
+            variant_bytes_written += f(writer)?;
+            Ok(variant_bytes_written)
+        })
+    }
+}
+
+impl<T: io::Write + io::Seek> DbusWriter<T> {
+    /// Same as `write_array` but streams the elements of `iter` instead of requiring a slice.
+    /// The length prefix is written as placeholder first and patched
+    /// once all elements have been written.
+    pub fn write_array_from_iter<T1, I>(
+        &mut self,
+        iter: I,
+        element_signature: &Signature,
+    ) -> Result<u64>
+    where
+        T1: DbusWrite,
+        I: IntoIterator<Item = T1>,
+    {
+        self.nested(|writer| writer.write_array_elements_from_iter(iter, element_signature))
+    }
+
+    fn write_array_elements_from_iter<T1, I>(
+        &mut self,
+        iter: I,
+        element_signature: &Signature,
+    ) -> Result<u64>
+    where
+        T1: DbusWrite,
+        I: IntoIterator<Item = T1>,
+    {
+        let start = self.position();
+
+        self.write_padding(4)?;
+        let length_position = self.writer.writer.stream_position()?;
+        write_ordered!(self.endianness, self.writer, write_u32, 0)?;
 
-        bytes_written += self.write_u32::<T1>(a.len() as u32, bytes_written)?;
+        self.write_padding(element_signature.alignment())?;
+        let elements_start = self.position();
 
-        for x in a {
-            bytes_written += x.write::<_, T1>(self, bytes_written)?;
+        for x in iter {
+            x.write(self)?;
         }
 
-        Ok(bytes_written)
+        let array_len = self.position() - elements_start;
+        if array_len > u64::from(MAX_ARRAY_LENGTH) {
+            let str_err = format!(
+                "Array length `{}` exceeds maximum of {}",
+                array_len, MAX_ARRAY_LENGTH
+            );
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, str_err));
+        }
+
+        // Patch the placeholder on the inner writer, the position must not change
+        let end = self.writer.writer.stream_position()?;
+        self.writer.writer.seek(SeekFrom::Start(length_position))?;
+        write_ordered!(
+            self.endianness,
+            self.writer.writer,
+            write_u32,
+            array_len as u32
+        )?;
+        self.writer.writer.seek(SeekFrom::Start(end))?;
+
+        Ok(self.position() - start)
+    }
+}
+
+macro_rules! impl_dbus_write {
+    ($t:ty, $write:ident) => {
+        impl DbusWrite for $t {
+            fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64> {
+                writer.$write(*self)
+            }
+        }
+    };
+}
+
+impl DbusWrite for u8 {
+    fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64> {
+        writer.write_u8(*self)
+    }
+}
+
+impl_dbus_write!(bool, write_boolean);
+impl_dbus_write!(i16, write_i16);
+impl_dbus_write!(u16, write_u16);
+impl_dbus_write!(i32, write_i32);
+impl_dbus_write!(u32, write_u32);
+impl_dbus_write!(i64, write_i64);
+impl_dbus_write!(u64, write_u64);
+impl_dbus_write!(f64, write_f64);
+
+impl DbusWrite for String {
+    fn write(&self, writer: &mut DbusWriter<dyn io::Write + '_>) -> Result<u64> {
+        writer.write_string(self)
     }
 }